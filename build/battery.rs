@@ -0,0 +1,150 @@
+//! How the `battery` publisher measures the battery (`[battery]`). Anything left out is a LiPo cell
+//! powering the chip through VDDH, so `config::BATTERY` is always complete.
+
+use serde::{Deserialize, Serialize};
+use toml::Spanned;
+
+use crate::schema::{parse_pin, Board, ConfigError};
+
+/// Supplies the SAADC can sample without a pin, VDDH through its internal /5 divider
+pub const SUPPLY_INPUTS: &[&str] = &["vddh", "vdd"];
+/// In ms
+pub const PERIOD: u64 = 60_000;
+/// In %
+pub const LOW: u8 = 10;
+/// Discharge curve of a LiPo cell under light load
+const LIPO_CURVE: &[(u32, u8)] = &[
+	(4200, 100),
+	(4100, 90),
+	(4000, 78),
+	(3900, 65),
+	(3800, 50),
+	(3700, 30),
+	(3600, 15),
+	(3500, 5),
+	(3300, 0),
+];
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Battery {
+	/// One of `SUPPLY_INPUTS`, or an analog pin behind a divider
+	pub input: Option<Spanned<String>>,
+	/// Battery voltage over the voltage at `input`
+	pub divider: Option<Spanned<f32>>,
+	/// From full to empty
+	pub curve: Option<Spanned<Vec<Spanned<CurvePoint>>>>,
+	pub period: Option<u64>,
+	/// Charge in % under which the low battery hook runs
+	pub low: Option<Spanned<u8>>,
+}
+
+#[derive(Debug, Clone, Copy, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct CurvePoint {
+	pub mv: u32,
+	pub percent: u8,
+}
+
+/// `[battery]` with the defaults filled in
+#[derive(Serialize)]
+pub struct BatterySettings {
+	pub input: String,
+	pub divider: f32,
+	pub curve: Vec<CurvePoint>,
+	pub period: u64,
+	pub low: u8,
+}
+
+impl Board {
+	pub fn uses_battery(&self) -> bool {
+		self.global.publishers.iter().any(|p| p.get_ref() == "battery")
+	}
+
+	/// Pin the battery is measured on, unless it's a supply of the chip
+	pub fn battery_pin(&self) -> Option<&Spanned<String>> {
+		self.battery
+			.as_ref()
+			.and_then(|battery| battery.input.as_ref())
+			.filter(|input| !SUPPLY_INPUTS.contains(&input.get_ref().as_str()) && parse_pin(input).is_ok())
+	}
+
+	pub(crate) fn validate_battery(&self, errors: &mut Vec<ConfigError>) {
+		let Some(battery) = &self.battery else {
+			return;
+		};
+
+		if let Some(input) = &battery.input {
+			if !SUPPLY_INPUTS.contains(&input.get_ref().as_str()) && parse_pin(input).is_err() {
+				errors.push(ConfigError::new(
+					input,
+					format!(
+						"Unknown battery input `{}`, expected one of {:?} or an analog pin",
+						input.get_ref(),
+						SUPPLY_INPUTS
+					),
+				));
+			}
+		}
+
+		if let Some(divider) = battery.divider.as_ref().filter(|divider| *divider.get_ref() < 1.0) {
+			errors.push(ConfigError::new(
+				divider,
+				"A divider can only lower the voltage, so it's at least 1",
+			));
+		}
+
+		if let Some(curve) = &battery.curve {
+			if curve.get_ref().is_empty() {
+				errors.push(ConfigError::new(curve, "The discharge curve needs at least one point"));
+			}
+
+			let mut previous: Option<CurvePoint> = None;
+			for point in curve.get_ref() {
+				let current = *point.get_ref();
+				if current.percent > 100 {
+					errors.push(ConfigError::new(point, "Charges go up to 100%"));
+				}
+				if previous.is_some_and(|previous| current.mv >= previous.mv || current.percent > previous.percent) {
+					errors.push(ConfigError::new(
+						point,
+						"The discharge curve goes from full to empty, with the voltage going down",
+					));
+				}
+				previous = Some(current);
+			}
+		}
+
+		if let Some(low) = battery.low.as_ref().filter(|low| *low.get_ref() > 100) {
+			errors.push(ConfigError::new(low, "Charges go up to 100%"));
+		}
+	}
+
+	pub fn battery_settings(&self) -> BatterySettings {
+		let battery = self.battery.as_ref();
+
+		BatterySettings {
+			input: battery.and_then(|battery| battery.input.as_ref()).map_or(
+				"vddh".to_owned(),
+				|input| match parse_pin(input) {
+					Ok(pin) => pin.to_string(),
+					Err(_) => input.get_ref().clone(),
+				},
+			),
+			divider: battery
+				.and_then(|battery| battery.divider.as_ref())
+				.map_or(1.0, |divider| *divider.get_ref()),
+			curve: match battery.and_then(|battery| battery.curve.as_ref()) {
+				Some(curve) => curve.get_ref().iter().map(|point| *point.get_ref()).collect(),
+				None => LIPO_CURVE
+					.iter()
+					.map(|&(mv, percent)| CurvePoint { mv, percent })
+					.collect(),
+			},
+			period: battery.and_then(|battery| battery.period).unwrap_or(PERIOD),
+			low: battery
+				.and_then(|battery| battery.low.as_ref())
+				.map_or(LOW, |low| *low.get_ref()),
+		}
+	}
+}
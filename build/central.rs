@@ -0,0 +1,110 @@
+//! How the `ble_central` publisher finds the keyboard or mouse it relays (`[ble_central]`). The
+//! address is required, so the board never bonds with whatever HID device happens to advertise
+//! nearby; anything else left out gets its default, so `config::BLE_CENTRAL` is always complete.
+
+use serde::{Deserialize, Serialize};
+use toml::Spanned;
+
+use crate::schema::{one_of, Board, ConfigError};
+
+/// What the board can show while pairing with the device - it has nothing to type a passkey the
+/// device shows with
+pub const CENTRAL_IO_CAPABILITIES: &[&str] = &["None", "DisplayOnly"];
+/// Rows and columns of a keymap the device's keys go through, one position per HID usage: the row is
+/// the high nibble of the usage and the column the low one
+pub const USAGE_GRID: (usize, usize) = (16, 16);
+/// In ms
+pub const PERIOD: u64 = 1000;
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct BleCentral {
+	/// `XX:XX:XX:XX:XX:XX`, the only device to connect to - required, `Option` for a located error
+	pub address: Option<Spanned<String>>,
+	/// One of `CENTRAL_IO_CAPABILITIES`
+	pub io_capabilities: Option<Spanned<String>>,
+	/// Between losing the device and looking for it again
+	pub period: Option<u64>,
+}
+
+/// `[ble_central]` with the defaults filled in
+#[derive(Serialize)]
+pub struct BleCentralSettings {
+	/// Validated, so always there
+	pub address: String,
+	pub io_capabilities: String,
+	pub period: u64,
+	/// Whether the keys go through the `keymap` middleware, laid out as `USAGE_GRID`
+	pub keymap: bool,
+}
+
+/// Bytes of `XX:XX:XX:XX:XX:XX`, most significant first
+pub fn parse_address(address: &str) -> Option<[u8; 6]> {
+	let mut bytes = [0u8; 6];
+	let mut parts = address.split(':');
+	for byte in &mut bytes {
+		let part = parts.next().filter(|part| part.len() == 2)?;
+		*byte = u8::from_str_radix(part, 16).ok()?;
+	}
+
+	parts.next().is_none().then_some(bytes)
+}
+
+impl Board {
+	pub fn uses_ble_central(&self) -> bool {
+		self.global.publishers.iter().any(|p| p.get_ref() == "ble_central")
+	}
+
+	pub(crate) fn validate_central(&self, errors: &mut Vec<ConfigError>) {
+		if self.uses_ble_central() && self.ble_link().central_connections == 0 {
+			let message = "`ble_central` needs at least one central connection";
+			errors.push(
+				match self.ble.as_ref().and_then(|ble| ble.central_connections.as_ref()) {
+					Some(connections) => ConfigError::new(connections, message),
+					None => ConfigError::global(message),
+				},
+			);
+		}
+
+		let address = self.ble_central.as_ref().and_then(|central| central.address.as_ref());
+		if self.uses_ble_central() && address.is_none() {
+			errors.push(ConfigError::global(
+				"`ble_central` needs the `address` of the device to pair with - it doesn't bond with just any",
+			));
+		}
+
+		let Some(central) = &self.ble_central else {
+			return;
+		};
+
+		if let Some(address) = central
+			.address
+			.as_ref()
+			.filter(|address| parse_address(address.get_ref()).is_none())
+		{
+			errors.push(ConfigError::new(
+				address,
+				format!("Invalid address `{}`, expected `XX:XX:XX:XX:XX:XX`", address.get_ref()),
+			));
+		}
+
+		if let Some(io_capabilities) = &central.io_capabilities {
+			errors.extend(one_of(io_capabilities, CENTRAL_IO_CAPABILITIES, "IO capability").err());
+		}
+	}
+
+	pub fn ble_central_settings(&self) -> BleCentralSettings {
+		let central = self.ble_central.as_ref();
+
+		BleCentralSettings {
+			address: central
+				.and_then(|central| central.address.as_ref())
+				.map_or(String::new(), |address| address.get_ref().clone()),
+			io_capabilities: central
+				.and_then(|central| central.io_capabilities.as_ref())
+				.map_or("None".to_owned(), |io_capabilities| io_capabilities.get_ref().clone()),
+			period: central.and_then(|central| central.period).unwrap_or(PERIOD),
+			keymap: self.global.middleware.iter().any(|m| m.get_ref() == "keymap"),
+		}
+	}
+}
@@ -0,0 +1,5 @@
+//! What the built-in components need from the board, shared with `board_main!` so the build script
+//! validates the config for what the firmware ends up setting up
+
+/// Components `board_main!` opens the database for, to keep their BLE bonds
+pub const DATABASE_COMPONENTS: &[&str] = &["ble_hid", "ble_central"];
@@ -0,0 +1,236 @@
+//! The flash the `ekv` database lives on (`[flash]`) - a QSPI chip, or the `STORAGE` region split
+//! off the end of `FLASH` in `memory.x` with `backend = "internal"`. Anything left out is the
+//! nRF52840 DK's MX25R6435F, which the firmware used to assume, so `config::FLASH` is always
+//! complete.
+
+use serde::{Deserialize, Serialize};
+use toml::Spanned;
+
+use crate::components::DATABASE_COMPONENTS;
+use crate::pin::PinSpec;
+use crate::schema::{one_of, parse_pin, Board, ConfigError};
+
+pub const BACKENDS: &[&str] = &["qspi", "internal"];
+pub const READ_OPCODES: &[&str] = &["FASTREAD", "READ2O", "READ2IO", "READ4O", "READ4IO"];
+pub const WRITE_OPCODES: &[&str] = &["PP", "PP2O", "PP4O", "PP4IO"];
+/// In MHz, the ones of `qspi::Frequency` that are whole
+pub const FREQUENCIES: &[&str] = &["M32", "M16", "M8", "M4", "M2"];
+/// Where the chip keeps its Quad Enable bit - `None` for chips that don't have one or ship with it set
+pub const QUAD_ENABLE: &[&str] = &["None", "Sr1Bit6", "Sr2Bit1"];
+
+/// Flash split off the end of `FLASH` for the database of `internal` boards
+pub const STORAGE_SIZE: &str = "64K";
+
+/// Pin keys of `[flash]` and the DK's pins
+const PINS: [(&str, &str); 6] = [
+	("sck", "0.19"),
+	("csn", "0.17"),
+	("io0", "0.20"),
+	("io1", "0.21"),
+	("io2", "0.22"),
+	("io3", "0.23"),
+];
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Flash {
+	pub backend: Option<Spanned<String>>,
+	pub sck: Option<Spanned<String>>,
+	pub csn: Option<Spanned<String>>,
+	pub io0: Option<Spanned<String>>,
+	pub io1: Option<Spanned<String>>,
+	pub io2: Option<Spanned<String>>,
+	pub io3: Option<Spanned<String>>,
+	/// In bytes
+	pub capacity: Option<Spanned<u32>>,
+	pub read_opcode: Option<Spanned<String>>,
+	pub write_opcode: Option<Spanned<String>>,
+	pub frequency: Option<Spanned<String>>,
+	pub quad_enable: Option<Spanned<String>>,
+	/// Manufacturer, memory type and capacity bytes the chip answers `0x9F` with, e.g. `0xC22817`
+	pub jedec_id: Option<Spanned<u32>>,
+}
+
+/// `[flash]` with the defaults filled in
+#[derive(Serialize)]
+pub struct FlashSettings {
+	pub backend: String,
+	pub sck: String,
+	pub csn: String,
+	pub io0: String,
+	pub io1: String,
+	pub io2: String,
+	pub io3: String,
+	pub capacity: u32,
+	pub read_opcode: String,
+	pub write_opcode: String,
+	pub frequency: String,
+	pub quad_enable: String,
+	pub jedec_id: u32,
+}
+
+impl Flash {
+	fn pin(&self, key: &str) -> Option<&Spanned<String>> {
+		match key {
+			"sck" => self.sck.as_ref(),
+			"csn" => self.csn.as_ref(),
+			"io0" => self.io0.as_ref(),
+			"io1" => self.io1.as_ref(),
+			"io2" => self.io2.as_ref(),
+			"io3" => self.io3.as_ref(),
+			_ => None,
+		}
+	}
+}
+
+/// A flash pin, either set by the board or left to the default
+pub enum FlashPin<'a> {
+	Configured(&'a Spanned<String>),
+	Default(PinSpec),
+}
+
+impl Board {
+	/// Whether `board_main!` opens the database, for a component of `DATABASE_COMPONENTS`
+	pub fn uses_flash(&self) -> bool {
+		let global = &self.global;
+		self.flash.is_some()
+			|| global
+				.publishers
+				.iter()
+				.chain(&global.middleware)
+				.chain(&global.subscribers)
+				.any(|component| DATABASE_COMPONENTS.contains(&component.get_ref().as_str()))
+	}
+
+	/// Whether the database needs the QSPI peripheral and its pins
+	pub fn uses_qspi(&self) -> bool {
+		let backend = self.flash.as_ref().and_then(|flash| flash.backend.as_ref());
+		self.uses_flash() && backend.map_or(true, |backend| backend.get_ref() == "qspi")
+	}
+
+	/// Whether the database lives in the `STORAGE` region of the chip's own flash
+	pub fn uses_internal_flash(&self) -> bool {
+		self.uses_flash() && !self.uses_qspi()
+	}
+
+	/// `memory.x` for the board: `STORAGE` gets split off the end of `FLASH` only when the database
+	/// lives there, but its bounds are always there for the firmware to link against
+	pub fn memory_x(&self, memory: &str) -> String {
+		let mut out = String::new();
+		let mut found = false;
+
+		for line in memory.lines() {
+			let region = line
+				.trim_start()
+				.strip_prefix("FLASH :")
+				.and_then(|region| region.trim().strip_prefix("ORIGIN ="))
+				.and_then(|region| region.split_once(", LENGTH ="))
+				.map(|(origin, length)| (origin.trim(), length.trim()));
+			found |= region.is_some();
+
+			match region {
+				Some((origin, length)) if self.uses_internal_flash() => {
+					out += &format!(
+						"  FLASH : ORIGIN = {}, LENGTH = {} - {}\n",
+						origin, length, STORAGE_SIZE
+					);
+					out += "  /* Database of the board, `flash.backend = \"internal\"` */\n";
+					out += &format!(
+						"  STORAGE : ORIGIN = {} + {} - {}, LENGTH = {}\n",
+						origin, length, STORAGE_SIZE, STORAGE_SIZE
+					);
+				},
+				_ => {
+					out += line;
+					out.push('\n');
+				},
+			}
+		}
+		assert!(found, "memory.x has no `FLASH : ORIGIN = ..., LENGTH = ...` region");
+
+		out += if self.uses_internal_flash() {
+			"\n__storage_start = ORIGIN(STORAGE);\n__storage_end = ORIGIN(STORAGE) + LENGTH(STORAGE);\n"
+		} else {
+			"\n/* No database in the internal flash, `STORAGE` is empty */\n__storage_start = ORIGIN(FLASH) + LENGTH(FLASH);\n__storage_end = __storage_start;\n"
+		};
+
+		out
+	}
+
+	/// Pins of the flash by their `[flash]` key
+	pub fn flash_pins(&self) -> Vec<(&'static str, FlashPin<'_>)> {
+		PINS.iter()
+			.map(|&(key, default)| {
+				let pin = match self.flash.as_ref().and_then(|flash| flash.pin(key)) {
+					Some(pin) => FlashPin::Configured(pin),
+					None => FlashPin::Default(default.parse().unwrap()),
+				};
+				(key, pin)
+			})
+			.collect()
+	}
+
+	pub(crate) fn validate_flash(&self, errors: &mut Vec<ConfigError>) {
+		let Some(flash) = &self.flash else {
+			return;
+		};
+
+		for (value, allowed, what) in [
+			(&flash.backend, BACKENDS, "flash backend"),
+			(&flash.read_opcode, READ_OPCODES, "read opcode"),
+			(&flash.write_opcode, WRITE_OPCODES, "write opcode"),
+			(&flash.frequency, FREQUENCIES, "frequency"),
+			(&flash.quad_enable, QUAD_ENABLE, "quad enable method"),
+		] {
+			if let Some(value) = value {
+				errors.extend(one_of(value, allowed, what).err());
+			}
+		}
+
+		if let Some(capacity) = flash
+			.capacity
+			.as_ref()
+			.filter(|capacity| !capacity.get_ref().is_power_of_two())
+		{
+			errors.push(ConfigError::new(
+				capacity,
+				"The flash capacity has to be a power of two",
+			));
+		}
+		if let Some(id) = flash.jedec_id.as_ref().filter(|id| *id.get_ref() > 0xFF_FFFF) {
+			errors.push(ConfigError::new(id, "JEDEC IDs are 3 bytes"));
+		}
+	}
+
+	pub fn flash_settings(&self) -> FlashSettings {
+		let flash = self.flash.as_ref();
+		let mut pins = self.flash_pins().into_iter().map(|(_, pin)| match pin {
+			FlashPin::Configured(pin) => parse_pin(pin).map_or(pin.get_ref().clone(), |pin| pin.to_string()),
+			FlashPin::Default(pin) => pin.to_string(),
+		});
+		let string = |value: Option<&Spanned<String>>, default: &str| {
+			value.map_or(default.to_owned(), |value| value.get_ref().clone())
+		};
+
+		FlashSettings {
+			backend: string(flash.and_then(|flash| flash.backend.as_ref()), "qspi"),
+			sck: pins.next().unwrap(),
+			csn: pins.next().unwrap(),
+			io0: pins.next().unwrap(),
+			io1: pins.next().unwrap(),
+			io2: pins.next().unwrap(),
+			io3: pins.next().unwrap(),
+			// The chip has 8 MB, but that's twice what `ekv` can use
+			capacity: flash
+				.and_then(|flash| flash.capacity.as_ref())
+				.map_or(4 * 1024 * 1024, |capacity| *capacity.get_ref()),
+			read_opcode: string(flash.and_then(|flash| flash.read_opcode.as_ref()), "READ4IO"),
+			write_opcode: string(flash.and_then(|flash| flash.write_opcode.as_ref()), "PP4IO"),
+			frequency: string(flash.and_then(|flash| flash.frequency.as_ref()), "M32"),
+			quad_enable: string(flash.and_then(|flash| flash.quad_enable.as_ref()), "Sr1Bit6"),
+			jedec_id: flash
+				.and_then(|flash| flash.jedec_id.as_ref())
+				.map_or(0xC2_2817, |id| *id.get_ref()),
+		}
+	}
+}
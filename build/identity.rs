@@ -0,0 +1,232 @@
+//! How the board identifies itself over USB (`[usb]`) and BLE (`[ble]`). Anything left out gets
+//! filled in here, so the generated `config::USB` and `config::BLE` are always complete. The radio
+//! keys of `[ble]` are in `link.rs`.
+
+use serde::{Deserialize, Serialize};
+use toml::Spanned;
+
+use crate::link::BleLink;
+use crate::schema::{one_of, Board, ConfigError};
+
+pub const VENDOR_ID: u16 = 0xC0DE;
+pub const PRODUCT_ID: u16 = 0xCAFE;
+pub const MANUFACTURER: &str = "PubSubinator";
+/// In mA
+pub const MAX_POWER: u16 = 100;
+/// In ms
+pub const POLL_INTERVAL: u8 = 60;
+/// Generic keyboard
+pub const APPEARANCE: u16 = 0x03C1;
+pub const VENDOR_ID_SOURCE: &str = "UsbIF";
+
+/// What fits in the advertisement next to the flags, services and appearance
+const BLE_NAME_MAX: usize = 16;
+/// Size of the device information service strings
+const BLE_STRING_MAX: usize = 32;
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Usb {
+	pub vendor_id: Option<u16>,
+	pub product_id: Option<u16>,
+	pub manufacturer: Option<String>,
+	pub product: Option<String>,
+	pub serial_number: Option<String>,
+	pub max_power: Option<Spanned<u16>>,
+	pub poll_interval: Option<Spanned<u8>>,
+	/// Defaults to `global.version`
+	pub device_release: Option<u16>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Ble {
+	pub name: Option<Spanned<String>>,
+	pub manufacturer: Option<Spanned<String>>,
+	pub model_number: Option<Spanned<String>>,
+	pub serial_number: Option<Spanned<String>>,
+	pub appearance: Option<u16>,
+	pub vendor_id_source: Option<Spanned<String>>,
+	pub vendor_id: Option<u16>,
+	pub product_id: Option<u16>,
+	/// Defaults to `global.version`
+	pub product_version: Option<u16>,
+
+	// Radio, see `link.rs` - intervals and timeouts in ms unless said otherwise
+	pub fast_interval: Option<Spanned<f32>>,
+	/// In s
+	pub fast_timeout: Option<Spanned<u16>>,
+	pub slow_interval: Option<Spanned<f32>>,
+	/// In s, 0 to advertise until a host connects
+	pub advertising_timeout: Option<u16>,
+	/// In dBm
+	pub tx_power: Option<Spanned<i8>>,
+	pub min_interval: Option<Spanned<f32>>,
+	pub max_interval: Option<Spanned<f32>>,
+	pub latency: Option<Spanned<u16>>,
+	pub supervision_timeout: Option<Spanned<u16>>,
+	pub idle_latency: Option<Spanned<u16>>,
+	/// 0 to never relax the connection
+	pub idle_after: Option<u64>,
+	pub att_mtu: Option<Spanned<u16>>,
+	/// In 1.25 ms units
+	pub event_length: Option<Spanned<u16>>,
+	pub peripheral_connections: Option<Spanned<u8>>,
+	pub central_connections: Option<Spanned<u8>>,
+}
+
+/// `[usb]` with the defaults filled in
+#[derive(Serialize)]
+pub struct UsbIdentity {
+	pub vendor_id: u16,
+	pub product_id: u16,
+	pub manufacturer: String,
+	pub product: String,
+	pub serial_number: String,
+	pub max_power: u16,
+	pub poll_interval: u8,
+	pub device_release: u16,
+}
+
+/// `[ble]` with the defaults filled in
+#[derive(Serialize)]
+pub struct BleIdentity {
+	pub name: String,
+	pub manufacturer: String,
+	pub model_number: String,
+	pub serial_number: String,
+	pub appearance: u16,
+	pub vendor_id_source: String,
+	pub vendor_id: u16,
+	pub product_id: u16,
+	pub product_version: u16,
+	#[serde(flatten)]
+	pub link: BleLink,
+}
+
+/// Parse a `major[.minor[.patch]]` version into the BCD `0xJJMN` of USB's `bcdDevice`, which the
+/// BLE PnP ID reuses
+pub fn parse_version(version: &Spanned<String>) -> Result<u16, ConfigError> {
+	let invalid =
+		|reason: &str| ConfigError::new(version, format!("Invalid version `{}` - {}", version.get_ref(), reason));
+
+	let parts = version
+		.get_ref()
+		.split('.')
+		.map(|part| part.parse::<u16>())
+		.collect::<Result<Vec<u16>, _>>()
+		.map_err(|_| invalid("expected `major.minor.patch`"))?;
+
+	let (major, minor, patch) = match parts[..] {
+		[major] => (major, 0, 0),
+		[major, minor] => (major, minor, 0),
+		[major, minor, patch] => (major, minor, patch),
+		_ => return Err(invalid("expected `major.minor.patch`")),
+	};
+	if major > 99 || minor > 9 || patch > 9 {
+		return Err(invalid("USB only has room for 0-99.0-9.0-9"));
+	}
+
+	Ok((major / 10) << 12 | (major % 10) << 8 | minor << 4 | patch)
+}
+
+fn max_len(value: &Spanned<String>, max: usize, errors: &mut Vec<ConfigError>) {
+	if value.get_ref().len() > max {
+		errors.push(ConfigError::new(
+			value,
+			format!("`{}` is longer than {} bytes", value.get_ref(), max),
+		));
+	}
+}
+
+impl Board {
+	pub(crate) fn validate_identity(&self, errors: &mut Vec<ConfigError>) {
+		errors.extend(parse_version(&self.global.version).err());
+
+		if let Some(usb) = &self.usb {
+			if let Some(max_power) = usb.max_power.as_ref().filter(|power| *power.get_ref() > 500) {
+				errors.push(ConfigError::new(max_power, "USB can't draw more than 500 mA"));
+			}
+			if let Some(interval) = usb.poll_interval.as_ref().filter(|interval| *interval.get_ref() == 0) {
+				errors.push(ConfigError::new(
+					interval,
+					"The polling interval has to be at least 1 ms",
+				));
+			}
+		}
+
+		let name = self.ble.as_ref().and_then(|ble| ble.name.as_ref());
+		match name {
+			Some(name) => max_len(name, BLE_NAME_MAX, errors),
+			None if self.has_feature("ble") && self.global.name.len() > BLE_NAME_MAX =>
+				errors.push(ConfigError::global(format!(
+					"`global.name` is too long to be advertised, set a shorter `ble.name` ({} bytes at most)",
+					BLE_NAME_MAX
+				))),
+			None => {},
+		}
+
+		let Some(ble) = &self.ble else {
+			return;
+		};
+		for value in [&ble.manufacturer, &ble.model_number, &ble.serial_number]
+			.into_iter()
+			.flatten()
+		{
+			max_len(value, BLE_STRING_MAX, errors);
+		}
+		if let Some(source) = &ble.vendor_id_source {
+			errors.extend(one_of(source, &["BluetoothSIG", "UsbIF"], "vendor ID source").err());
+		}
+	}
+
+	/// Only meaningful once the board passed validation
+	fn version_bcd(&self) -> u16 {
+		parse_version(&self.global.version).unwrap_or_default()
+	}
+
+	pub fn usb_identity(&self) -> UsbIdentity {
+		let global = &self.global;
+		let usb = self.usb.as_ref();
+
+		UsbIdentity {
+			vendor_id: usb.and_then(|usb| usb.vendor_id).unwrap_or(VENDOR_ID),
+			product_id: usb.and_then(|usb| usb.product_id).unwrap_or(PRODUCT_ID),
+			manufacturer: usb
+				.and_then(|usb| usb.manufacturer.clone())
+				.unwrap_or(MANUFACTURER.to_owned()),
+			product: usb.and_then(|usb| usb.product.clone()).unwrap_or(global.name.clone()),
+			serial_number: usb
+				.and_then(|usb| usb.serial_number.clone())
+				.unwrap_or(global.serial.clone()),
+			max_power: usb
+				.and_then(|usb| usb.max_power.as_ref())
+				.map_or(MAX_POWER, |power| *power.get_ref()),
+			poll_interval: usb
+				.and_then(|usb| usb.poll_interval.as_ref())
+				.map_or(POLL_INTERVAL, |interval| *interval.get_ref()),
+			device_release: usb.and_then(|usb| usb.device_release).unwrap_or(self.version_bcd()),
+		}
+	}
+
+	pub fn ble_identity(&self) -> BleIdentity {
+		let global = &self.global;
+		let ble = self.ble.as_ref();
+		let string = |value: Option<&Spanned<String>>, default: &str| {
+			value.map_or(default.to_owned(), |value| value.get_ref().clone())
+		};
+
+		BleIdentity {
+			name: string(ble.and_then(|ble| ble.name.as_ref()), &global.name),
+			manufacturer: string(ble.and_then(|ble| ble.manufacturer.as_ref()), MANUFACTURER),
+			model_number: string(ble.and_then(|ble| ble.model_number.as_ref()), &global.name),
+			serial_number: string(ble.and_then(|ble| ble.serial_number.as_ref()), &global.serial),
+			appearance: ble.and_then(|ble| ble.appearance).unwrap_or(APPEARANCE),
+			vendor_id_source: string(ble.and_then(|ble| ble.vendor_id_source.as_ref()), VENDOR_ID_SOURCE),
+			vendor_id: ble.and_then(|ble| ble.vendor_id).unwrap_or(VENDOR_ID),
+			product_id: ble.and_then(|ble| ble.product_id).unwrap_or(PRODUCT_ID),
+			product_version: ble.and_then(|ble| ble.product_version).unwrap_or(self.version_bcd()),
+			link: self.ble_link(),
+		}
+	}
+}
@@ -0,0 +1,219 @@
+//! How the board advertises and keeps its BLE connections - the radio keys of `[ble]`, next to the
+//! identity ones. Anything left out is what the firmware used to hardcode, or Apple's accessory
+//! guidelines where it had nothing, so the generated `config::BLE` is always complete.
+
+use serde::Serialize;
+use toml::Spanned;
+
+use crate::schema::{Board, ConfigError};
+
+/// TX powers of the nRF52840 in dBm, as in `TxPower`
+pub const TX_POWERS: &[i8] = &[-40, -20, -16, -12, -8, -4, 0, 2, 3, 4, 5, 6, 7, 8];
+/// In ms
+pub const FAST_INTERVAL: f32 = 20.0;
+/// In s
+pub const FAST_TIMEOUT: u16 = 30;
+/// In ms
+pub const SLOW_INTERVAL: f32 = 417.5;
+/// In ms
+pub const MIN_INTERVAL: f32 = 7.5;
+/// In ms
+pub const MAX_INTERVAL: f32 = 15.0;
+/// In ms
+pub const SUPERVISION_TIMEOUT: u16 = 2000;
+/// Connection events skipped while idle
+pub const IDLE_LATENCY: u16 = 30;
+/// In ms
+pub const IDLE_AFTER: u64 = 5000;
+pub const ATT_MTU: u16 = 256;
+/// In 1.25 ms units
+pub const EVENT_LENGTH: u16 = 24;
+pub const PERIPHERAL_CONNECTIONS: u8 = 3;
+pub const CENTRAL_CONNECTIONS: u8 = 3;
+
+/// Most the SoftDevice takes in 10 ms units
+const FAST_TIMEOUT_MAX: u16 = 655;
+
+/// Radio keys of `[ble]` with the defaults filled in
+#[derive(Serialize)]
+pub struct BleLink {
+	pub fast_interval: f32,
+	pub fast_timeout: u16,
+	pub slow_interval: f32,
+	pub advertising_timeout: u16,
+	pub tx_power: i8,
+	pub min_interval: f32,
+	pub max_interval: f32,
+	pub latency: u16,
+	pub supervision_timeout: u16,
+	pub idle_latency: u16,
+	pub idle_after: u64,
+	pub att_mtu: u16,
+	pub event_length: u16,
+	pub peripheral_connections: u8,
+	pub central_connections: u8,
+}
+
+fn in_range<T: PartialOrd + Copy>(
+	value: &Option<Spanned<T>>,
+	range: std::ops::RangeInclusive<T>,
+	message: &str,
+	errors: &mut Vec<ConfigError>,
+) {
+	if let Some(value) = value.as_ref().filter(|value| !range.contains(value.get_ref())) {
+		errors.push(ConfigError::new(value, message));
+	}
+}
+
+fn value<T: Copy>(value: Option<&Spanned<T>>, default: T) -> T {
+	value.map_or(default, |value| *value.get_ref())
+}
+
+impl Board {
+	pub(crate) fn validate_link(&self, errors: &mut Vec<ConfigError>) {
+		let Some(ble) = &self.ble else {
+			return;
+		};
+
+		for interval in [&ble.fast_interval, &ble.slow_interval] {
+			in_range(
+				interval,
+				20.0..=10240.0,
+				"Advertising intervals go from 20 ms to 10.24 s",
+				errors,
+			);
+		}
+		in_range(
+			&ble.fast_timeout,
+			0..=FAST_TIMEOUT_MAX,
+			"Fast advertising can't last more than 655 s",
+			errors,
+		);
+		if let Some(tx_power) = ble
+			.tx_power
+			.as_ref()
+			.filter(|power| !TX_POWERS.contains(power.get_ref()))
+		{
+			errors.push(ConfigError::new(
+				tx_power,
+				format!(
+					"Unsupported TX power {} dBm, expected one of {:?}",
+					tx_power.get_ref(),
+					TX_POWERS
+				),
+			));
+		}
+
+		for interval in [&ble.min_interval, &ble.max_interval] {
+			in_range(
+				interval,
+				7.5..=4000.0,
+				"Connection intervals go from 7.5 ms to 4 s",
+				errors,
+			);
+		}
+		for latency in [&ble.latency, &ble.idle_latency] {
+			in_range(latency, 0..=499, "At most 499 connection events can be skipped", errors);
+		}
+		in_range(
+			&ble.supervision_timeout,
+			100..=32000,
+			"Supervision timeouts go from 100 ms to 32 s",
+			errors,
+		);
+
+		let link = self.ble_link();
+		if let Some(min) = ble
+			.min_interval
+			.as_ref()
+			.filter(|_| link.min_interval > link.max_interval)
+		{
+			errors.push(ConfigError::new(min, "`min_interval` is above `max_interval`"));
+		}
+		// Both sides have to have missed a couple of events at the longest interval before giving up
+		let idle_latency = if link.idle_after == 0 { 0 } else { link.idle_latency };
+		let latency = link.latency.max(idle_latency) as f32;
+		if link.supervision_timeout as f32 <= (1.0 + latency) * link.max_interval * 2.0 {
+			let message = format!(
+				"A supervision timeout of {} ms is too short for `max_interval` {} ms with {} events skipped",
+				link.supervision_timeout, link.max_interval, latency
+			);
+			errors.push(match &ble.supervision_timeout {
+				Some(timeout) => ConfigError::new(timeout, message),
+				None => ConfigError::global(format!("{} - set a longer `ble.supervision_timeout`", message)),
+			});
+		}
+
+		// `memory.x` leaves the SoftDevice the RAM it needs with the defaults, so the sizing keys can
+		// only shrink what it allocates
+		in_range(
+			&ble.att_mtu,
+			23..=ATT_MTU,
+			&format!(
+				"ATT MTUs go from 23 to {} bytes in the RAM `memory.x` leaves the SoftDevice",
+				ATT_MTU
+			),
+			errors,
+		);
+		in_range(
+			&ble.event_length,
+			2..=EVENT_LENGTH,
+			&format!(
+				"Connection events go from 2.5 ms (2) to {} ms ({}) in the RAM `memory.x` leaves the SoftDevice",
+				EVENT_LENGTH as f32 * 1.25,
+				EVENT_LENGTH
+			),
+			errors,
+		);
+		in_range(
+			&ble.peripheral_connections,
+			1..=PERIPHERAL_CONNECTIONS,
+			&format!(
+				"The board needs at least one peripheral connection to be connected to, and the RAM \
+				 `memory.x` leaves the SoftDevice fits {}",
+				PERIPHERAL_CONNECTIONS
+			),
+			errors,
+		);
+		in_range(
+			&ble.central_connections,
+			0..=CENTRAL_CONNECTIONS,
+			&format!(
+				"The RAM `memory.x` leaves the SoftDevice fits {} central connections at most",
+				CENTRAL_CONNECTIONS
+			),
+			errors,
+		);
+	}
+
+	pub fn ble_link(&self) -> BleLink {
+		let ble = self.ble.as_ref();
+
+		BleLink {
+			fast_interval: value(ble.and_then(|ble| ble.fast_interval.as_ref()), FAST_INTERVAL),
+			fast_timeout: value(ble.and_then(|ble| ble.fast_timeout.as_ref()), FAST_TIMEOUT),
+			slow_interval: value(ble.and_then(|ble| ble.slow_interval.as_ref()), SLOW_INTERVAL),
+			advertising_timeout: ble.and_then(|ble| ble.advertising_timeout).unwrap_or(0),
+			tx_power: value(ble.and_then(|ble| ble.tx_power.as_ref()), 0),
+			min_interval: value(ble.and_then(|ble| ble.min_interval.as_ref()), MIN_INTERVAL),
+			max_interval: value(ble.and_then(|ble| ble.max_interval.as_ref()), MAX_INTERVAL),
+			latency: value(ble.and_then(|ble| ble.latency.as_ref()), 0),
+			supervision_timeout: value(
+				ble.and_then(|ble| ble.supervision_timeout.as_ref()),
+				SUPERVISION_TIMEOUT,
+			),
+			idle_latency: value(ble.and_then(|ble| ble.idle_latency.as_ref()), IDLE_LATENCY),
+			idle_after: ble.and_then(|ble| ble.idle_after).unwrap_or(IDLE_AFTER),
+			att_mtu: value(ble.and_then(|ble| ble.att_mtu.as_ref()), ATT_MTU),
+			event_length: value(ble.and_then(|ble| ble.event_length.as_ref()), EVENT_LENGTH),
+			peripheral_connections: value(
+				ble.and_then(|ble| ble.peripheral_connections.as_ref()),
+				PERIPHERAL_CONNECTIONS,
+			),
+			central_connections: value(
+				ble.and_then(|ble| ble.central_connections.as_ref()),
+				CENTRAL_CONNECTIONS,
+			),
+		}
+	}
+}
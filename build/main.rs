@@ -0,0 +1,286 @@
+//! This build script writes the `memory.x` of the board, from `build/memory.x`
+//! with the database's `STORAGE` region split off `FLASH` when the board keeps
+//! it in the internal flash, into a directory where the linker can always
+//! find it at build time. It's kept out of the crate root on purpose, as the
+//! linker would pick that one over ours. By requesting that Cargo re-run the
+//! build script whenever `build/memory.x` is changed, updating it ensures a
+//! rebuild of the application with the new memory settings.
+
+mod battery;
+mod central;
+mod components;
+mod flash;
+mod identity;
+mod link;
+mod manifest;
+// The firmware's pin parser, to check pins the way it will read them
+#[allow(dead_code)]
+#[path = "../src/gpio/pin.rs"]
+mod pin;
+mod resolve;
+mod schema;
+
+use convert_case::{Case, Casing};
+use std::fs::File;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::{env, fs, process};
+use toml;
+
+use manifest::Manifest;
+use resolve::Loader;
+use schema::{Board, ConfigError};
+
+/// Cargo feature that takes over the critical section from the SoftDevice
+const SINGLE_CORE_FEATURE: &str = "critical-section-single-core";
+
+fn value_to_rust(section: &str, key: &str, value: &toml::Value) -> Result<String, String> {
+	Ok(match value {
+		toml::Value::String(s) => format!("{:?}", s),
+		toml::Value::Integer(i) => i.to_string(),
+		// Debug keeps the `.0` of whole numbers, which would be integers to Rust otherwise
+		toml::Value::Float(f) => format!("{:?}", f),
+		toml::Value::Boolean(b) => b.to_string(),
+		toml::Value::Array(arr) => {
+			let elements = arr
+				.iter()
+				.map(|v| value_to_rust(section, key, v))
+				.collect::<Result<Vec<_>, _>>()?
+				.join(", ");
+			format!("vec![{}]", elements)
+		},
+		toml::Value::Table(table) => {
+			let field_type = key.to_case(Case::Pascal);
+			let fields = table
+				.iter()
+				.map(|(k, v)| Ok(format!("{}: {}", k, value_to_rust(section, key, v)?)))
+				.collect::<Result<Vec<_>, String>>()?
+				.join(",\n\t");
+			format!("{}Config{}Type {{\n\t{}\n}}", section, field_type, fields) // Assuming you have a corresponding struct
+		},
+		toml::Value::Datetime(_) => return Err(format!("`{}` of `{}` can't be a date", key, section)),
+	})
+}
+
+fn handle_global_section(global: &schema::Global) {
+	println!("cargo:rustc-env=DEVICE_NAME={}", global.name);
+	println!("cargo:rustc-env=DEVICE_VERSION={}", global.version.get_ref());
+	println!("cargo:rustc-env=DEVICE_SERIAL={}", global.serial);
+
+	for (key, components) in [
+		("publishers", &global.publishers),
+		("middleware", &global.middleware),
+		("subscribers", &global.subscribers),
+	] {
+		let joined = components
+			.iter()
+			.map(|v| v.get_ref().as_str())
+			.collect::<Vec<&str>>()
+			.join(",");
+		println!("cargo:rustc-env=PUBSUB_{}={}", key.to_uppercase(), joined);
+	}
+}
+
+/// Cargo features a board needs to be built with
+fn cargo_features(board: &Board) -> Vec<&'static str> {
+	let mut features = schema::FEATURES
+		.iter()
+		.copied()
+		.filter(|feature| board.has_feature(feature))
+		.collect::<Vec<&str>>();
+
+	// The SoftDevice provides the critical section otherwise
+	if !board.has_feature("ble") {
+		features.push(SINGLE_CORE_FEATURE);
+	}
+
+	features
+}
+
+/// Optional dependencies can only be left out by Cargo, so all we can do is check that the features
+/// of the board were the ones enabled
+fn check_cargo_features(board: &Board) -> Result<(), ConfigError> {
+	let enabled =
+		|feature: &str| env::var_os(format!("CARGO_FEATURE_{}", feature.to_uppercase().replace('-', "_"))).is_some();
+	let expected = cargo_features(board);
+
+	let mismatched = schema::FEATURES
+		.iter()
+		.chain([SINGLE_CORE_FEATURE].iter())
+		.any(|feature| enabled(feature) != expected.contains(feature));
+	if !mismatched {
+		return Ok(());
+	}
+
+	let flags = ["debug"]
+		.into_iter()
+		.filter(|feature| enabled(feature))
+		.chain(expected)
+		.collect::<Vec<&str>>()
+		.join(",");
+
+	Err(ConfigError::global(format!(
+		"The enabled Cargo features don't match `global.features`, build with `--no-default-features --features {}`",
+		flags
+	)))
+}
+
+/// `BOARD_CONFIG` is either the name of a board in `boards/` or the path to a board file, absolute
+/// or relative to the crate root
+fn board_path(board: &str) -> PathBuf {
+	let root = PathBuf::from(env::var_os("CARGO_MANIFEST_DIR").unwrap());
+	let path = Path::new(board);
+
+	if path.extension().is_some_and(|ext| ext == "toml") {
+		// Joining an absolute path just gives it back - `board_main!` needs relative ones anchored
+		root.join(path)
+	} else {
+		root.join("boards").join(board.to_owned() + ".toml")
+	}
+}
+
+/// Stop the build once the errors of the board config were reported
+fn fail(board_path: &Path) -> ! {
+	eprintln!("error: invalid board config {}", board_path.display());
+	process::exit(1);
+}
+
+fn main() {
+	// `memory.x` goes to our output directory, on the linker search path, once the board says
+	// whether the database takes a part of the flash
+	let out = &PathBuf::from(env::var_os("OUT_DIR").unwrap());
+	println!("cargo:rustc-link-search={}", out.display());
+
+	// By default, Cargo will re-run a build script whenever
+	// any file in the project changes. By specifying `build/memory.x`
+	// here, we ensure the build script is only re-run when
+	// it or the board is changed.
+	println!("cargo:rerun-if-changed=build/memory.x");
+
+	println!("cargo:rustc-link-arg-bins=--nmagic");
+	println!("cargo:rustc-link-arg-bins=-Tlink.x");
+	println!("cargo:rustc-link-arg-bins=-Tdefmt.x");
+
+	println!("cargo:rerun-if-env-changed=BOARD_CONFIG");
+	let board_path = board_path(&env::var("BOARD_CONFIG").unwrap_or("example".to_owned()));
+	let resolved = Loader::default().resolve(&board_path).unwrap_or_else(|e| {
+		eprintln!("error: {}", e.error.display(&e.path, &e.source));
+		fail(&board_path)
+	});
+	for file in resolved.files() {
+		println!("cargo:rerun-if-changed={}", file.display());
+	}
+	let report = |errors: &[ConfigError]| -> ! {
+		for error in errors {
+			let (path, source, error) = resolved.locate(error);
+			eprintln!("error: {}", error.display(path, source));
+		}
+		fail(&board_path)
+	};
+
+	// Boards made of several files are checked in their merged form, the errors mapped back to
+	// the file each value comes from
+	let board = resolved.source.as_str();
+	let merged_path = if resolved.files().count() == 1 {
+		board_path.clone()
+	} else {
+		let merged_path = out.join("board.toml");
+		fs::write(&merged_path, board).unwrap();
+		merged_path
+	};
+	// Read by `board_main!` to generate the components and tasks of the board, naming the board
+	// the user picked in its errors
+	println!("cargo:rustc-env=BOARD_CONFIG_PATH={}", board_path.display());
+	println!("cargo:rustc-env=BOARD_CONFIG_RESOLVED={}", merged_path.display());
+
+	let sections = resolved.board.clone();
+	let typed = toml::from_str::<Board>(board).unwrap_or_else(|e| report(&[ConfigError::from(e)]));
+
+	let errors = typed.validate(&sections);
+	if !errors.is_empty() {
+		report(&errors);
+	}
+	if let Err(e) = check_cargo_features(&typed) {
+		report(&[e]);
+	}
+
+	handle_global_section(&typed.global);
+
+	let memory = fs::read_to_string("build/memory.x").unwrap();
+	fs::write(out.join("memory.x"), typed.memory_x(&memory)).unwrap();
+
+	// Also copied to `BOARD_MANIFEST` when set, as `OUT_DIR` is hard to find for other tools
+	let manifest = serde_json::to_string_pretty(&Manifest::new(&typed)).unwrap();
+	fs::write(out.join("board.json"), &manifest).unwrap();
+	println!("cargo:rerun-if-env-changed=BOARD_MANIFEST");
+	if let Some(path) = env::var_os("BOARD_MANIFEST") {
+		fs::write(&path, &manifest).unwrap_or_else(|e| panic!("Could not write the board manifest: {}", e));
+	}
+
+	// Always generated complete, whether or not the board has the sections
+	let mut sections = sections;
+	sections.insert("usb".to_owned(), toml::Value::try_from(typed.usb_identity()).unwrap());
+	sections.insert("ble".to_owned(), toml::Value::try_from(typed.ble_identity()).unwrap());
+	sections.insert(
+		"flash".to_owned(),
+		toml::Value::try_from(typed.flash_settings()).unwrap(),
+	);
+	sections.insert(
+		"battery".to_owned(),
+		toml::Value::try_from(typed.battery_settings()).unwrap(),
+	);
+	sections.insert(
+		"ble_central".to_owned(),
+		toml::Value::try_from(typed.ble_central_settings()).unwrap(),
+	);
+	let mut ble_hid = toml::Table::new();
+	ble_hid.insert("reports".to_owned(), typed.ble_hid_reports().into());
+	ble_hid.insert("io_capabilities".to_owned(), typed.ble_io_capabilities().into());
+	ble_hid.insert(
+		"require_encryption".to_owned(),
+		typed
+			.ble_hid
+			.as_ref()
+			.and_then(|ble_hid| ble_hid.require_encryption)
+			.unwrap_or(false)
+			.into(),
+	);
+	sections.insert("ble_hid".to_owned(), ble_hid.into());
+	let mut output = toml::Table::new();
+	output.insert("mode".to_owned(), typed.output_mode().into());
+	sections.insert("output".to_owned(), output.into());
+
+	// `include!`d by `crate::config`
+	let mut config = File::create(out.join("config.rs")).unwrap();
+
+	writeln!(config, "use alloc::vec;").unwrap();
+	writeln!(config, "use lazy_static::lazy_static;").unwrap();
+	writeln!(config, "use crate::config_types::*;\n").unwrap();
+
+	writeln!(config, "lazy_static! {{").unwrap();
+	for (section, items) in sections {
+		if section == "global" {
+			continue;
+		}
+
+		let name = section.to_case(Case::UpperSnake);
+		let field_type = section.to_case(Case::Pascal);
+		let fields = items
+			.as_table()
+			.unwrap()
+			.into_iter()
+			.map(|(k, v)| Ok(format!("\t\t{}: {},\n", k, value_to_rust(&field_type, k, v)?)))
+			.collect::<Result<String, String>>()
+			.unwrap_or_else(|e| report(&[ConfigError::global(e)]));
+
+		writeln!(
+			config,
+			"\tpub static ref {0}: {1}Config = {1}Config {{\n{2}\t\t..Default::default()\n\t}};\n",
+			name, field_type, fields
+		)
+		.unwrap();
+	}
+	writeln!(config, "}}").unwrap();
+
+	config.sync_all().unwrap();
+}
@@ -0,0 +1,201 @@
+//! Machine-readable description of the resolved board, for tooling that renders layouts, diffs
+//! revisions or checks pins without parsing Rust
+
+use serde::Serialize;
+
+use crate::battery::BatterySettings;
+use crate::flash::FlashSettings;
+use crate::identity::{BleIdentity, UsbIdentity};
+use crate::pin::PinSpec;
+use crate::schema::{self, Board};
+
+#[derive(Serialize)]
+pub struct Manifest<'a> {
+	name: &'a str,
+	version: &'a str,
+	serial: &'a str,
+	features: Vec<&'a str>,
+	publishers: Vec<&'a str>,
+	middleware: Vec<&'a str>,
+	subscribers: Vec<&'a str>,
+	pins: Vec<Pin>,
+	matrix: Option<Matrix<'a>>,
+	analog: Option<Analog<'a>>,
+	keymap: Option<Keymap<'a>>,
+	hid: Vec<Hid<'a>>,
+	usb: Option<UsbIdentity>,
+	ble: Option<BleIdentity>,
+	flash: Option<FlashSettings>,
+	battery: Option<BatterySettings>,
+}
+
+#[derive(Serialize)]
+struct Pin {
+	/// Spelled `<port>.<pin>`, whichever alias the board used
+	pin: String,
+	port: u8,
+	number: u8,
+	/// Config key the pin comes from, e.g. `matrix.inputs[2]`
+	used_by: String,
+}
+
+#[derive(Serialize)]
+struct Matrix<'a> {
+	rows: usize,
+	cols: usize,
+	direction: &'a str,
+	inputs: Vec<&'a str>,
+	outputs: Vec<&'a str>,
+	period: Option<u64>,
+	idle_period: Option<u64>,
+	idle_after: Option<u64>,
+}
+
+#[derive(Serialize)]
+struct Analog<'a> {
+	inputs: Vec<&'a str>,
+	period: Option<u64>,
+}
+
+#[derive(Serialize)]
+struct Keymap<'a> {
+	/// Keycode names by layer, row and column
+	layers: Vec<Vec<Vec<&'a str>>>,
+	hold_time: Option<u64>,
+}
+
+#[derive(Serialize)]
+struct Hid<'a> {
+	transport: &'a str,
+	/// Report descriptor of `report_maps` - BLE lists one per report ID
+	report: &'a str,
+}
+
+fn names(values: &[toml::Spanned<String>]) -> Vec<&str> {
+	values.iter().map(|value| value.get_ref().as_str()).collect()
+}
+
+impl<'a> Manifest<'a> {
+	/// Describe a board that passed validation
+	pub fn new(board: &'a Board) -> Self {
+		let global = &board.global;
+		let mut pins = Vec::new();
+		let mut claim = |pin: &toml::Spanned<String>, used_by: String| {
+			let spec = schema::parse_pin(pin).expect("pins are validated");
+			pins.push(Pin {
+				pin: spec.to_string(),
+				port: spec.port,
+				number: spec.pin,
+				used_by,
+			});
+		};
+
+		let matrix = board.matrix.as_ref().map(|matrix| {
+			for (i, input) in matrix.inputs.iter().enumerate() {
+				claim(&input.pin, format!("matrix.inputs[{}]", i));
+			}
+			for (i, output) in matrix.outputs.iter().enumerate() {
+				claim(&output.pin, format!("matrix.outputs[{}]", i));
+			}
+
+			let (rows, cols) = matrix.geometry();
+			Matrix {
+				rows,
+				cols,
+				direction: matrix.direction.get_ref(),
+				inputs: matrix.inputs.iter().map(|input| input.pin.get_ref().as_str()).collect(),
+				outputs: matrix
+					.outputs
+					.iter()
+					.map(|output| output.pin.get_ref().as_str())
+					.collect(),
+				period: matrix.period,
+				idle_period: matrix.idle_period,
+				idle_after: matrix.idle_after,
+			}
+		});
+
+		let analog = board.analog.as_ref().map(|analog| {
+			for (i, input) in analog.inputs.iter().enumerate() {
+				claim(input, format!("analog.inputs[{}]", i));
+			}
+
+			Analog {
+				inputs: names(&analog.inputs),
+				period: analog.period,
+			}
+		});
+
+		if let Some(input) = board.battery_pin().filter(|_| board.uses_battery()) {
+			claim(input, "battery.input".to_owned());
+		}
+
+		let flash = board.uses_flash().then(|| board.flash_settings());
+		if let Some(settings) = flash.as_ref().filter(|_| board.uses_qspi()) {
+			for (key, pin) in [
+				("sck", &settings.sck),
+				("csn", &settings.csn),
+				("io0", &settings.io0),
+				("io1", &settings.io1),
+				("io2", &settings.io2),
+				("io3", &settings.io3),
+			] {
+				let spec = pin.parse::<PinSpec>().expect("pins are validated");
+				pins.push(Pin {
+					pin: spec.to_string(),
+					port: spec.port,
+					number: spec.pin,
+					used_by: format!("flash.{}", key),
+				});
+			}
+		}
+
+		let keymap = board.keymap.as_ref().map(|keymap| Keymap {
+			layers: keymap
+				.layers
+				.get_ref()
+				.iter()
+				.map(|layer| layer.get_ref().iter().map(|row| names(row.get_ref())).collect())
+				.collect(),
+			hold_time: keymap.hold_time,
+		});
+
+		let mut hid = Vec::new();
+		for subscriber in &global.subscribers {
+			match subscriber.get_ref().as_str() {
+				"usb_hid" => hid.push(Hid {
+					transport: "usb",
+					report: board
+						.usb_hid
+						.as_ref()
+						.and_then(|usb_hid| usb_hid.report.as_ref())
+						.map_or("KeyboardReport", |report| report.get_ref()),
+				}),
+				"ble_hid" => hid.extend(board.ble_hid_reports().into_iter().map(|report| Hid {
+					transport: "ble",
+					report,
+				})),
+				_ => {},
+			}
+		}
+
+		Self {
+			name: &global.name,
+			version: global.version.get_ref(),
+			serial: &global.serial,
+			features: names(&global.features),
+			publishers: names(&global.publishers),
+			middleware: names(&global.middleware),
+			subscribers: names(&global.subscribers),
+			pins,
+			matrix,
+			analog,
+			keymap,
+			hid,
+			usb: board.has_feature("usb").then(|| board.usb_identity()),
+			ble: board.has_feature("ble").then(|| board.ble_identity()),
+			flash,
+			battery: board.uses_battery().then(|| board.battery_settings()),
+		}
+	}
+}
@@ -0,0 +1,301 @@
+//! Board files can build on others: `extends = "<board>"` starts from another board and
+//! `include = ["<file>", ...]` pulls in fragments such as keymaps, both relative to the file
+//! doing it. Everything is deep-merged - tables key by key, anything else (arrays included) gets
+//! replaced - with the base first, then the includes in order and the file itself last.
+
+use std::collections::BTreeMap;
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+use std::{fmt, fs};
+
+use serde::de::{Deserializer, MapAccess, SeqAccess, Visitor};
+use serde::Deserialize;
+use toml::{Spanned, Table, Value};
+
+use crate::schema::ConfigError;
+
+/// Keys of a board file that link it to others
+#[derive(Deserialize)]
+struct Links {
+	extends: Option<Spanned<String>>,
+	#[serde(default)]
+	include: Vec<Spanned<String>>,
+}
+
+/// A board file that couldn't be loaded, along with its source to locate the error in
+pub struct LoadError {
+	pub path: PathBuf,
+	pub source: String,
+	pub error: ConfigError,
+}
+
+/// Key path of a key or value of a board, array elements by index
+type KeyPath = Vec<String>;
+
+/// Where a key or value was written in a board file
+#[derive(Clone)]
+struct Origin {
+	/// Index in the files of the board
+	file: usize,
+	span: Range<usize>,
+	table: bool,
+}
+
+/// Origins by key path - `true` for the span of the key itself rather than its value
+type Origins = BTreeMap<(KeyPath, bool), Origin>;
+
+#[derive(Default)]
+pub struct Loader {
+	/// Every file that went into the board along with its source, to rebuild when any of them changes
+	files: Vec<(PathBuf, String)>,
+	/// Files being loaded, to catch cycles
+	stack: Vec<PathBuf>,
+}
+
+/// A board with everything it extends and includes merged in
+pub struct Resolved {
+	pub board: Table,
+	/// The merged board as TOML, what the errors are located in until `locate` maps them back
+	pub source: String,
+	files: Vec<(PathBuf, String)>,
+	/// Spans of `source` along with where they were written in the board files
+	spans: Vec<(Range<usize>, Origin)>,
+}
+
+/// Merge `over` into `base`: tables are merged key by key, anything else is replaced
+pub fn merge(base: &mut Table, over: Table) {
+	for (key, value) in over {
+		match (base.get_mut(&key), value) {
+			(Some(Value::Table(base)), Value::Table(over)) => merge(base, over),
+			(_, value) => {
+				base.insert(key, value);
+			},
+		}
+	}
+}
+
+/// Merge the origins of `over` into `base` the way `merge` does the values
+fn merge_origins(base: &mut Origins, over: Origins) {
+	for ((path, key), origin) in &over {
+		let merged = *key || origin.table && base.get(&(path.clone(), false)).is_some_and(|base| base.table);
+		if !merged {
+			base.retain(|(other, _), _| !(other.starts_with(path) && other.len() > path.len()));
+		}
+	}
+
+	base.extend(over);
+}
+
+impl Loader {
+	/// Load the board at `path` with everything it extends and includes merged in
+	pub fn resolve(mut self, path: &Path) -> Result<Resolved, LoadError> {
+		let (board, origins) = self.load(path)?;
+		let files = self.files;
+
+		// A board on its own is located in as is
+		let source = match &files[..] {
+			[(_, source)] => source.clone(),
+			_ => toml::to_string(&board).unwrap(),
+		};
+		let spans = spans(&source)
+			.into_iter()
+			.filter_map(|(path, span)| Some((span, origins.get(&path)?.clone())))
+			.collect();
+
+		Ok(Resolved {
+			board,
+			source,
+			files,
+			spans,
+		})
+	}
+
+	fn load(&mut self, path: &Path) -> Result<(Table, Origins), LoadError> {
+		let source = fs::read_to_string(path).map_err(|e| LoadError {
+			path: path.to_owned(),
+			source: String::new(),
+			error: ConfigError::global(format!("Could not read the board: {}", e)),
+		})?;
+		let file = self.files.len();
+		self.files.push((path.to_owned(), source.clone()));
+
+		let located = |error: ConfigError| LoadError {
+			path: path.to_owned(),
+			source: source.clone(),
+			error,
+		};
+		let parse_error = |e: toml::de::Error| located(ConfigError::from(e));
+
+		let mut own = toml::from_str::<Table>(&source).map_err(parse_error)?;
+		let links = toml::from_str::<Links>(&source).map_err(parse_error)?;
+		own.remove("extends");
+		own.remove("include");
+		let own_origins = spans(&source)
+			.into_iter()
+			.filter(|((path, _), _)| !matches!(path[0].as_str(), "extends" | "include"))
+			.map(|((path, key), span)| {
+				let table = !key && own_value(&own, &path).is_some_and(Value::is_table);
+				((path, key), Origin { file, span, table })
+			})
+			.collect::<Origins>();
+
+		let dir = path.parent().unwrap_or(Path::new("."));
+		let mut board = Table::new();
+		let mut origins = Origins::new();
+		self.stack.push(fs::canonicalize(path).unwrap_or(path.to_owned()));
+
+		for link in links.extends.iter().chain(links.include.iter()) {
+			let mut linked = dir.join(link.get_ref());
+			if linked.extension().is_none() {
+				linked.set_extension("toml");
+			}
+
+			let Ok(canonical) = fs::canonicalize(&linked) else {
+				return Err(located(ConfigError::new(
+					link,
+					format!("No board file {}", linked.display()),
+				)));
+			};
+			if self.stack.contains(&canonical) {
+				return Err(located(ConfigError::new(
+					link,
+					format!("{} ends up extending or including itself", linked.display()),
+				)));
+			}
+
+			let (linked, linked_origins) = self.load(&linked)?;
+			merge(&mut board, linked);
+			merge_origins(&mut origins, linked_origins);
+		}
+
+		self.stack.pop();
+		merge(&mut board, own);
+		merge_origins(&mut origins, own_origins);
+
+		Ok((board, origins))
+	}
+}
+
+/// Value of a board file at `path`
+fn own_value<'a>(table: &'a Table, path: &[String]) -> Option<&'a Value> {
+	let (first, rest) = path.split_first()?;
+	rest.iter().try_fold(table.get(first)?, |value, key| match value {
+		Value::Table(table) => table.get(key),
+		Value::Array(array) => array.get(key.parse::<usize>().ok()?),
+		_ => None,
+	})
+}
+
+impl Resolved {
+	/// Every file that went into the board, the board itself first
+	pub fn files(&self) -> impl Iterator<Item = &Path> {
+		self.files.iter().map(|(path, _)| path.as_path())
+	}
+
+	/// The file the error comes from along with its source, and the error located in it
+	pub fn locate(&self, error: &ConfigError) -> (&Path, &str, ConfigError) {
+		let (board, source) = &self.files[0];
+		if self.files.len() == 1 {
+			return (board, source, error.clone());
+		}
+
+		// The innermost key or value the error is in
+		let origin = error.span.as_ref().and_then(|span| {
+			self.spans
+				.iter()
+				.filter(|(inner, _)| inner.start <= span.start && span.end <= inner.end)
+				.min_by_key(|(inner, _)| inner.len())
+				.map(|(_, origin)| origin)
+		});
+
+		match origin {
+			Some(origin) => {
+				let (path, source) = &self.files[origin.file];
+				let error = ConfigError {
+					span: Some(origin.span.clone()),
+					message: error.message.clone(),
+				};
+				(path, source, error)
+			},
+			None => (board, source, ConfigError::global(error.message.clone())),
+		}
+	}
+}
+
+/// Span of every key and value of a TOML document
+fn spans(source: &str) -> Vec<((KeyPath, bool), Range<usize>)> {
+	// A document that doesn't parse isn't located in anyway
+	toml::from_str::<Spans>(source).map(|spans| spans.0).unwrap_or_default()
+}
+
+/// Spans of the keys and values in a value, by key path relative to it
+struct Spans(Vec<((KeyPath, bool), Range<usize>)>);
+
+impl Spans {
+	fn nest(&mut self, key: String, key_span: Option<Range<usize>>, value: Spanned<Spans>) {
+		if let Some(key_span) = key_span {
+			self.0.push(((vec![key.clone()], true), key_span));
+		}
+		self.0.push(((vec![key.clone()], false), value.span()));
+
+		for ((mut path, is_key), span) in value.into_inner().0 {
+			path.insert(0, key.clone());
+			self.0.push(((path, is_key), span));
+		}
+	}
+}
+
+impl<'de> Deserialize<'de> for Spans {
+	fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+		deserializer.deserialize_any(SpansVisitor)
+	}
+}
+
+struct SpansVisitor;
+
+impl<'de> Visitor<'de> for SpansVisitor {
+	type Value = Spans;
+
+	fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		f.write_str("a TOML value")
+	}
+
+	fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<Spans, A::Error> {
+		let mut spans = Spans(Vec::new());
+		while let Some(key) = map.next_key::<Spanned<String>>()? {
+			let span = key.span();
+			spans.nest(key.into_inner(), Some(span), map.next_value()?);
+		}
+		Ok(spans)
+	}
+
+	fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<Spans, A::Error> {
+		let mut spans = Spans(Vec::new());
+		let mut index = 0;
+		while let Some(value) = seq.next_element()? {
+			spans.nest(index.to_string(), None, value);
+			index += 1;
+		}
+		Ok(spans)
+	}
+
+	fn visit_bool<E>(self, _: bool) -> Result<Spans, E> {
+		Ok(Spans(Vec::new()))
+	}
+
+	fn visit_i64<E>(self, _: i64) -> Result<Spans, E> {
+		Ok(Spans(Vec::new()))
+	}
+
+	fn visit_u64<E>(self, _: u64) -> Result<Spans, E> {
+		Ok(Spans(Vec::new()))
+	}
+
+	fn visit_f64<E>(self, _: f64) -> Result<Spans, E> {
+		Ok(Spans(Vec::new()))
+	}
+
+	fn visit_str<E>(self, _: &str) -> Result<Spans, E> {
+		Ok(Spans(Vec::new()))
+	}
+}
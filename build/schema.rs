@@ -0,0 +1,550 @@
+//! Typed schema of the board config files, along with the checks that can't be expressed by types
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt;
+use std::ops::Range;
+use std::path::Path;
+use std::str::FromStr;
+
+use reactor::KeyCodeInt;
+use serde::Deserialize;
+use toml::Spanned;
+
+use crate::battery::Battery;
+use crate::central::{BleCentral, USAGE_GRID};
+use crate::flash::{Flash, FlashPin};
+use crate::identity::{Ble, Usb};
+use crate::pin::PinSpec;
+
+/// Components that don't need a config section to be constructed
+pub const BUILTIN_COMPONENTS: &[&str] = &[
+	"matrix",
+	"analog",
+	"battery",
+	"keymap",
+	"keyboard_report",
+	"joystick_6dof",
+	"usb_hid",
+	"ble_hid",
+	"ble_central",
+	"tracer",
+];
+pub const FEATURES: &[&str] = &["nrf52840", "ble", "usb"];
+/// Features the built-in components can't do without
+pub const COMPONENT_FEATURES: &[(&str, &str)] = &[
+	("usb_hid", "usb"),
+	("tracer", "usb"),
+	("ble_hid", "ble"),
+	("ble_central", "ble"),
+];
+/// Peripherals the built-in components take for themselves in `board_main!`
+pub const COMPONENT_PERIPHERALS: &[(&str, &str)] = &[("analog", "SAADC"), ("battery", "SAADC")];
+/// Reports of `report_maps` the BLE HID service can combine
+pub const BLE_REPORTS: &[&str] = &[
+	"KeyboardReport",
+	"MediaKeyboardReport",
+	"MouseReport",
+	"SpaceMouseReport",
+];
+/// What the board can show and take while pairing over BLE, as in `IoCapabilities` - `DisplayYesNo`
+/// is left out as numeric comparison needs LE Secure Connections
+pub const IO_CAPABILITIES: &[&str] = &["None", "DisplayOnly", "KeyboardOnly", "KeyboardDisplay"];
+/// How `output.mode` picks the transport of the reports when the board has both - USB once the host
+/// enumerated it, BLE once connected, or both at once
+pub const OUTPUT_MODES: &[&str] = &["Usb", "Ble", "Both"];
+
+/// Keycode names by layer, row and column
+type Layers = Spanned<Vec<Spanned<Vec<Spanned<Vec<Spanned<String>>>>>>>;
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Board {
+	pub global: Global,
+	pub matrix: Option<Matrix>,
+	pub keymap: Option<Keymap>,
+	pub analog: Option<Analog>,
+	pub battery: Option<Battery>,
+	pub usb_hid: Option<UsbHid>,
+	pub ble_hid: Option<BleHid>,
+	pub ble_central: Option<BleCentral>,
+	pub usb: Option<Usb>,
+	pub ble: Option<Ble>,
+	pub flash: Option<Flash>,
+	pub output: Option<Output>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Global {
+	pub name: String,
+	pub version: Spanned<String>,
+	pub serial: String,
+	#[serde(default)]
+	pub features: Vec<Spanned<String>>,
+	#[serde(default)]
+	pub publishers: Vec<Spanned<String>>,
+	#[serde(default)]
+	pub middleware: Vec<Spanned<String>>,
+	#[serde(default)]
+	pub subscribers: Vec<Spanned<String>>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Matrix {
+	pub inputs: Vec<MatrixInput>,
+	pub outputs: Vec<MatrixOutput>,
+	pub direction: Spanned<String>,
+	pub period: Option<u64>,
+	pub idle_period: Option<u64>,
+	pub idle_after: Option<u64>,
+}
+
+impl Matrix {
+	/// Rows and columns of the keys - the matrix reports (row, col) with the rows being the driven
+	/// side for Row2Col
+	pub fn geometry(&self) -> (usize, usize) {
+		match self.direction.get_ref().as_str() {
+			"Col2Row" => (self.inputs.len(), self.outputs.len()),
+			_ => (self.outputs.len(), self.inputs.len()),
+		}
+	}
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct MatrixInput {
+	pub pin: Spanned<String>,
+	pub pull: Spanned<String>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct MatrixOutput {
+	pub pin: Spanned<String>,
+	pub level: Spanned<String>,
+	pub drive: Spanned<String>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Keymap {
+	pub layers: Layers,
+	pub hold_time: Option<u64>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Analog {
+	pub inputs: Vec<Spanned<String>>,
+	pub period: Option<u64>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct UsbHid {
+	pub report: Option<Spanned<String>>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct BleHid {
+	/// Given report IDs from 1 in this order
+	pub reports: Option<Spanned<Vec<Spanned<String>>>>,
+	/// One of `IO_CAPABILITIES`
+	pub io_capabilities: Option<Spanned<String>>,
+	/// Only send reports once the link is encrypted
+	pub require_encryption: Option<bool>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Output {
+	/// One of `OUTPUT_MODES`
+	pub mode: Option<Spanned<String>>,
+}
+
+#[derive(Debug, Clone)]
+pub struct ConfigError {
+	pub span: Option<Range<usize>>,
+	pub message: String,
+}
+
+impl From<toml::de::Error> for ConfigError {
+	fn from(e: toml::de::Error) -> Self {
+		Self {
+			span: e.span(),
+			message: e.message().to_owned(),
+		}
+	}
+}
+
+impl ConfigError {
+	pub fn new<T>(spanned: &Spanned<T>, message: impl Into<String>) -> Self {
+		Self {
+			span: Some(spanned.span()),
+			message: message.into(),
+		}
+	}
+
+	pub fn global(message: impl Into<String>) -> Self {
+		Self {
+			span: None,
+			message: message.into(),
+		}
+	}
+
+	/// Render the error as `path:line:column: message`
+	pub fn display<'a>(&'a self, path: &'a Path, source: &'a str) -> impl fmt::Display + 'a {
+		struct Located<'a>(&'a ConfigError, &'a Path, &'a str);
+
+		impl fmt::Display for Located<'_> {
+			fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+				let Located(error, path, source) = self;
+				match &error.span {
+					Some(span) => {
+						let before = &source[..span.start.min(source.len())];
+						let line = before.matches('\n').count() + 1;
+						let column = before.len() - before.rfind('\n').map(|i| i + 1).unwrap_or(0) + 1;
+						write!(f, "{}:{}:{}: {}", path.display(), line, column, error.message)
+					},
+					None => write!(f, "{}: {}", path.display(), error.message),
+				}
+			}
+		}
+
+		Located(self, path, source)
+	}
+}
+
+/// Parse a pin the way the firmware will
+pub fn parse_pin(pin: &Spanned<String>) -> Result<PinSpec, ConfigError> {
+	PinSpec::from_str(pin.get_ref())
+		.map_err(|e| ConfigError::new(pin, format!("Invalid pin `{}` - {}", pin.get_ref(), e)))
+}
+
+pub fn one_of(value: &Spanned<String>, allowed: &[&str], what: &str) -> Result<(), ConfigError> {
+	if allowed.contains(&value.get_ref().as_str()) {
+		Ok(())
+	} else {
+		Err(ConfigError::new(
+			value,
+			format!("Unknown {} `{}`, expected one of {:?}", what, value.get_ref(), allowed),
+		))
+	}
+}
+
+impl Board {
+	pub fn has_feature(&self, feature: &str) -> bool {
+		self.global.features.iter().any(|f| f.get_ref() == feature)
+	}
+
+	/// Reports of the BLE HID service in report ID order - a keyboard unless `ble_hid.reports` says
+	/// otherwise
+	pub fn ble_hid_reports(&self) -> Vec<&str> {
+		match self.ble_hid.as_ref().and_then(|ble_hid| ble_hid.reports.as_ref()) {
+			Some(reports) => reports
+				.get_ref()
+				.iter()
+				.map(|report| report.get_ref().as_str())
+				.collect(),
+			None => vec!["KeyboardReport"],
+		}
+	}
+
+	/// `output.mode`, USB when it's there by default
+	pub fn output_mode(&self) -> &str {
+		self.output
+			.as_ref()
+			.and_then(|output| output.mode.as_ref())
+			.map_or("Usb", |mode| mode.get_ref())
+	}
+
+	/// `ble_hid.io_capabilities` or `None`, Just Works pairing
+	pub fn ble_io_capabilities(&self) -> &str {
+		self.ble_hid
+			.as_ref()
+			.and_then(|ble_hid| ble_hid.io_capabilities.as_ref())
+			.map_or("None", |io_capabilities| io_capabilities.get_ref())
+	}
+
+	/// Run every check, collecting all the errors instead of stopping at the first
+	pub fn validate(&self, sections: &toml::Table) -> Vec<ConfigError> {
+		let mut errors = Vec::new();
+
+		self.validate_components(sections, &mut errors);
+		self.validate_pins(&mut errors);
+		self.validate_keymap(&mut errors);
+		self.validate_identity(&mut errors);
+		self.validate_link(&mut errors);
+		self.validate_flash(&mut errors);
+		self.validate_battery(&mut errors);
+		self.validate_central(&mut errors);
+
+		for feature in &self.global.features {
+			errors.extend(one_of(feature, FEATURES, "feature").err());
+		}
+
+		if let Some(usb_hid) = &self.usb_hid {
+			if let Some(report) = &usb_hid.report {
+				errors.extend(one_of(report, &["KeyboardReport", "SpaceMouseReport"], "report").err());
+			}
+		}
+
+		if let Some(reports) = self.ble_hid.as_ref().and_then(|ble_hid| ble_hid.reports.as_ref()) {
+			if reports.get_ref().is_empty() {
+				errors.push(ConfigError::new(
+					reports,
+					"The BLE HID service needs at least one report",
+				));
+			}
+
+			let mut seen = BTreeSet::new();
+			for report in reports.get_ref() {
+				errors.extend(one_of(report, BLE_REPORTS, "report").err());
+				if !seen.insert(report.get_ref()) {
+					errors.push(ConfigError::new(
+						report,
+						format!("Report `{}` is listed twice", report.get_ref()),
+					));
+				}
+			}
+		}
+
+		if let Some(io_capabilities) = self
+			.ble_hid
+			.as_ref()
+			.and_then(|ble_hid| ble_hid.io_capabilities.as_ref())
+		{
+			errors.extend(one_of(io_capabilities, IO_CAPABILITIES, "IO capability").err());
+
+			// The passkey is typed as keys of the keymap
+			let keyboard = matches!(io_capabilities.get_ref().as_str(), "KeyboardOnly" | "KeyboardDisplay");
+			if keyboard && !self.global.middleware.iter().any(|m| m.get_ref() == "keymap") {
+				errors.push(ConfigError::new(
+					io_capabilities,
+					"Typing the passkey on the board needs the `keymap` middleware",
+				));
+			}
+		}
+
+		if let Some(mode) = self.output.as_ref().and_then(|output| output.mode.as_ref()) {
+			errors.extend(one_of(mode, OUTPUT_MODES, "output mode").err());
+
+			let subscribers = &self.global.subscribers;
+			if !["usb_hid", "ble_hid"]
+				.iter()
+				.all(|hid| subscribers.iter().any(|s| s.get_ref() == hid))
+			{
+				errors.push(ConfigError::new(
+					mode,
+					"There's nothing to pick without both `usb_hid` and `ble_hid`",
+				));
+			}
+		}
+
+		errors
+	}
+
+	fn validate_components(&self, sections: &toml::Table, errors: &mut Vec<ConfigError>) {
+		let mut seen = BTreeSet::new();
+		let mut taken = BTreeMap::new();
+		let components = self
+			.global
+			.publishers
+			.iter()
+			.chain(self.global.middleware.iter())
+			.chain(self.global.subscribers.iter());
+
+		for component in components {
+			let name = component.get_ref();
+			let missing = COMPONENT_FEATURES
+				.iter()
+				.find(|(c, feature)| c == name && !self.has_feature(feature));
+
+			if let Some((_, feature)) = missing {
+				errors.push(ConfigError::new(
+					component,
+					format!("Component `{}` needs the `{}` feature", name, feature),
+				));
+			}
+
+			for (_, peripheral) in COMPONENT_PERIPHERALS.iter().filter(|(c, _)| c == name) {
+				if let Some(other) = taken.insert(*peripheral, name).filter(|other| *other != name) {
+					errors.push(ConfigError::new(
+						component,
+						format!(
+							"`{}` and `{}` can't share the {} - use one or the other",
+							name, other, peripheral
+						),
+					));
+				}
+			}
+
+			if !seen.insert(name) {
+				errors.push(ConfigError::new(
+					component,
+					format!("Component `{}` is listed twice", name),
+				));
+			} else if !BUILTIN_COMPONENTS.contains(&name.as_str()) && !sections.contains_key(name) {
+				errors.push(ConfigError::new(
+					component,
+					format!(
+						"Unknown component `{}` - it's neither built-in nor has a config section",
+						name
+					),
+				));
+			}
+		}
+	}
+
+	fn validate_pins(&self, errors: &mut Vec<ConfigError>) {
+		// Pins the board doesn't mention but still ends up using
+		let mut implicit = BTreeMap::new();
+		if self.uses_qspi() {
+			for (key, pin) in self.flash_pins() {
+				if let FlashPin::Default(pin) = pin {
+					implicit.insert(pin, key);
+				}
+			}
+		}
+
+		let mut used = BTreeSet::new();
+		let mut claim = |pin: &Spanned<String>, analog: bool, errors: &mut Vec<ConfigError>| {
+			let parsed = match parse_pin(pin) {
+				Ok(parsed) => parsed,
+				Err(e) => return errors.push(e),
+			};
+
+			if let Some(reserved) = parsed.reserved() {
+				errors.push(ConfigError::new(
+					pin,
+					format!("Pin `{}` is reserved for {}", pin.get_ref(), reserved),
+				));
+			}
+
+			if let Some(key) = implicit.get(&parsed) {
+				errors.push(ConfigError::new(
+					pin,
+					format!(
+						"Pin `{}` is taken by the QSPI flash (`flash.{}`) - set other flash pins in `[flash]`",
+						pin.get_ref(),
+						key
+					),
+				));
+			}
+
+			if analog && !parsed.is_analog() {
+				errors.push(ConfigError::new(
+					pin,
+					format!("Pin `{}` isn't an analog input", pin.get_ref()),
+				));
+			}
+
+			if !used.insert(parsed) {
+				errors.push(ConfigError::new(
+					pin,
+					format!("Pin `{}` is used more than once", pin.get_ref()),
+				));
+			}
+		};
+
+		if self.uses_qspi() {
+			for (_, pin) in self.flash_pins() {
+				if let FlashPin::Configured(pin) = pin {
+					claim(pin, false, errors);
+				}
+			}
+		}
+
+		if let Some(matrix) = &self.matrix {
+			for input in &matrix.inputs {
+				claim(&input.pin, false, errors);
+				errors.extend(one_of(&input.pull, &["None", "Up", "Down"], "pull").err());
+			}
+			for output in &matrix.outputs {
+				claim(&output.pin, false, errors);
+				errors.extend(one_of(&output.level, &["Low", "High"], "level").err());
+				errors.extend(one_of(&output.drive, &["Standard", "High"], "drive").err());
+			}
+			errors.extend(one_of(&matrix.direction, &["Col2Row", "Row2Col"], "direction").err());
+		}
+
+		if let Some(analog) = &self.analog {
+			for input in &analog.inputs {
+				claim(input, true, errors);
+			}
+		}
+
+		if let Some(input) = self.battery_pin() {
+			claim(input, true, errors);
+		}
+	}
+
+	fn validate_keymap(&self, errors: &mut Vec<ConfigError>) {
+		let Some(keymap) = &self.keymap else {
+			return;
+		};
+
+		for layer in keymap.layers.get_ref() {
+			for row in layer.get_ref() {
+				for key in row.get_ref() {
+					if KeyCodeInt::from_str(key.get_ref()).is_err() {
+						errors.push(ConfigError::new(
+							key,
+							format!("Unknown keycode or action `{}`", key.get_ref()),
+						));
+					}
+				}
+			}
+		}
+
+		// Keys of a `ble_central` device are laid out by their usage, which a matrix can't share
+		let ((rows, cols), layout) = match (&self.matrix, self.uses_ble_central()) {
+			(Some(_), true) => {
+				errors.push(ConfigError::new(
+					&keymap.layers,
+					"The keymap can't be laid out for both the matrix and `ble_central`",
+				));
+				return;
+			},
+			(Some(matrix), false) => (matrix.geometry(), "the matrix"),
+			(None, true) => (USAGE_GRID, "the usage grid of `ble_central`"),
+			(None, false) => {
+				if !keymap.layers.get_ref().is_empty() {
+					errors.push(ConfigError::new(&keymap.layers, "The keymap needs a `matrix` section"));
+				}
+				return;
+			},
+		};
+
+		for (index, layer) in keymap.layers.get_ref().iter().enumerate() {
+			if layer.get_ref().len() != rows {
+				errors.push(ConfigError::new(
+					layer,
+					format!(
+						"Layer {} has {} rows but {} has {}",
+						index,
+						layer.get_ref().len(),
+						layout,
+						rows
+					),
+				));
+			}
+
+			for row in layer.get_ref() {
+				if row.get_ref().len() != cols {
+					errors.push(ConfigError::new(
+						row,
+						format!(
+							"Row has {} keys but {} has {} columns",
+							row.get_ref().len(),
+							layout,
+							cols
+						),
+					));
+				}
+			}
+		}
+	}
+}
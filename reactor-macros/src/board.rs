@@ -0,0 +1,211 @@
+use std::str::FromStr;
+use std::{env, fs};
+
+use proc_macro2::{Span, TokenStream};
+use quote::{format_ident, quote};
+use syn::{Error, Ident, Result};
+
+use crate::components::DATABASE_COMPONENTS;
+use crate::pin::PinSpec;
+
+/// Components that need to share a peripheral or a task with others
+#[derive(Default)]
+struct Needs {
+	usb: bool,
+	softdevice: bool,
+	/// The database, for the BLE bonds
+	db: bool,
+	ble: bool,
+}
+
+/// Turn a pin of the board config into the identifier of its peripheral, `P0_04`
+fn pin_ident(pin: &str) -> Result<Ident> {
+	match PinSpec::from_str(pin) {
+		Ok(PinSpec { port, pin }) => Ok(format_ident!("P{}_{:02}", port, pin)),
+		Err(e) => Err(Error::new(Span::call_site(), format!("Invalid pin `{}` - {}", pin, e))),
+	}
+}
+
+fn string_array<'a>(table: Option<&'a toml::Value>, key: &str) -> Vec<&'a str> {
+	table
+		.and_then(|t| t.get(key))
+		.and_then(|v| v.as_array())
+		.map(|arr| arr.iter().filter_map(|v| v.as_str()).collect())
+		.unwrap_or_default()
+}
+
+/// Statements that construct the component `name` into a `&'static mut` binding of the same name
+fn component(name: &str, board: &toml::Table, needs: &mut Needs) -> Result<TokenStream> {
+	let ident = Ident::new(name, Span::call_site());
+	let section = board.get(name);
+	let config = Ident::new(&name.to_uppercase(), Span::call_site());
+	let mut setup = TokenStream::new();
+	needs.db |= DATABASE_COMPONENTS.contains(&name);
+
+	let init = match name {
+		"keyboard_report" => quote! { reactor::keyboard_report::KeyboardReportMid::default() },
+		"joystick_6dof" => quote! { joystick_6dof_mid::Joystick6DOFMid::default() },
+		"analog" => {
+			let pins = string_array(section, "inputs")
+				.into_iter()
+				.map(pin_ident)
+				.collect::<Result<Vec<Ident>>>()?;
+
+			quote! {
+				Analog::new(p.SAADC, [#(Into::<saadc::AnyInput>::into(p.#pins)),*])
+					.with_period(Duration::from_millis(config::ANALOG.period))
+			}
+		},
+		"battery" => {
+			let input = match section.and_then(|s| s.get("input")).and_then(|i| i.as_str()) {
+				Some("vdd") => quote! { saadc::VddInput },
+				Some(pin) if pin != "vddh" => {
+					let pin = pin_ident(pin)?;
+					quote! { p.#pin }
+				},
+				_ => quote! { saadc::VddhDiv5Input },
+			};
+
+			quote! { Battery::new(p.SAADC, #input, &config::BATTERY) }
+		},
+		"usb_hid" => {
+			needs.usb = true;
+
+			let report = section
+				.and_then(|s| s.get("report"))
+				.and_then(|r| r.as_str())
+				.unwrap_or("KeyboardReport");
+			let report = Ident::new(report, Span::call_site());
+
+			quote! { UsbHid::new::<report_maps::#report>(&mut usb_builder) }
+		},
+		"ble_hid" => {
+			needs.softdevice = true;
+			needs.ble = true;
+
+			quote! {
+				BleHid {
+					softdevice: sd,
+					server,
+					channel: CHANNEL.subscriber().unwrap(),
+					mouse_buttons: 0,
+				}
+			}
+		},
+		"ble_central" => {
+			needs.softdevice = true;
+
+			quote! { BleCentral::new(sd, db) }
+		},
+		"tracer" => {
+			needs.usb = true;
+
+			setup = quote! {
+				spawner.spawn(trace::trace_usb_task(trace::usb_class(&mut usb_builder))).unwrap();
+			};
+
+			quote! { trace::Tracer }
+		},
+		_ if section.is_some() => quote! { config::#config.build() },
+		_ =>
+			return Err(Error::new(
+				Span::call_site(),
+				format!("Unknown component `{}` - it has no config section either", name),
+			)),
+	};
+
+	Ok(quote! {
+		let #ident = make_static!(#init);
+		#setup
+		info!("{} initialized", #name);
+	})
+}
+
+pub fn expand() -> Result<TokenStream> {
+	let env = |name: &str| {
+		env::var(name).map_err(|_| {
+			Error::new(
+				Span::call_site(),
+				format!("{} is not set - it's emitted by the build script", name),
+			)
+		})
+	};
+	let board_path = env("BOARD_CONFIG_PATH")?;
+	// The board with everything it extends and includes merged in, the board itself otherwise
+	let resolved_path = env("BOARD_CONFIG_RESOLVED")?;
+	let board = fs::read_to_string(&resolved_path)
+		.map_err(|e| Error::new(Span::call_site(), format!("Could not read {}: {}", resolved_path, e)))?;
+	let board = board
+		.parse::<toml::Table>()
+		.map_err(|e| Error::new(Span::call_site(), format!("Could not parse {}: {}", resolved_path, e)))?;
+
+	let global = board.get("global");
+	let publishers = string_array(global, "publishers");
+	let middleware = string_array(global, "middleware");
+	let subscribers = string_array(global, "subscribers");
+
+	let features = string_array(global, "features");
+
+	let mut needs = Needs::default();
+	let components = publishers
+		.iter()
+		.chain(middleware.iter())
+		.chain(subscribers.iter())
+		.map(|name| component(name, &board, &mut needs))
+		.collect::<Result<Vec<TokenStream>>>()
+		.map_err(|e| Error::new(e.span(), format!("{}: {}", board_path, e)))?;
+
+	let publishers = publishers
+		.iter()
+		.map(|name| Ident::new(name, Span::call_site()))
+		.collect::<Vec<Ident>>();
+	let middleware = middleware.iter().map(|name| Ident::new(name, Span::call_site()));
+	let subscribers = subscribers.iter().map(|name| Ident::new(name, Span::call_site()));
+
+	// The SoftDevice also provides the USB VBUS events when it's there
+	needs.softdevice |= needs.usb && features.contains(&"ble");
+
+	let usb_init = needs.usb.then(|| quote! { let mut usb_builder = usb_init(p.USBD); });
+	let usb_spawn = needs
+		.usb
+		.then(|| quote! { spawner.spawn(usb_task(usb_builder)).unwrap(); });
+	let sd_init = needs.softdevice.then(|| quote! { let sd = get_softdevice(); });
+	let sd_spawn = needs
+		.softdevice
+		.then(|| quote! { spawner.spawn(softdevice_task(sd)).unwrap(); });
+	let db_init = needs.db.then(|| quote! { let db = get_db(sd).await; });
+	let ble_init = needs.ble.then(|| {
+		quote! {
+			let server = make_static!(ble_hid::Server::new(sd).unwrap());
+			server.init();
+		}
+	});
+	let ble_spawn = needs
+		.ble
+		.then(|| quote! { spawner.spawn(ble_hid_task(sd, server, db)).unwrap(); });
+	let publishers_spawn = (!publishers.is_empty()).then(|| {
+		quote! { spawner.spawn(publishers_task!([#(#publishers),*])).unwrap(); }
+	});
+
+	Ok(quote! {
+		#[embassy_executor::main]
+		async fn main(spawner: Spawner) {
+			#[allow(unused_variables)]
+			let p = init();
+
+			#usb_init
+			#sd_init
+			#db_init
+			#ble_init
+
+			#(#components)*
+
+			#usb_spawn
+			#sd_spawn
+			#ble_spawn
+
+			#publishers_spawn
+			spawner.spawn(subscribers_task!(CHANNEL, [#(#subscribers),*], [#(#middleware),*])).unwrap();
+		}
+	})
+}
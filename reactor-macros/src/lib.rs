@@ -1,6 +1,15 @@
 #![feature(proc_macro_diagnostic)]
 extern crate proc_macro;
 
+mod board;
+// Shared with the build script, so it validates what the components need
+#[path = "../../build/components.rs"]
+mod components;
+// Shared with the firmware and its build script
+#[allow(dead_code)]
+#[path = "../../src/gpio/pin.rs"]
+mod pin;
+
 use std::env;
 
 use proc_macro::TokenStream;
@@ -43,31 +52,101 @@ pub fn subscribers_task(input: TokenStream) -> TokenStream {
 
 	let expanded = quote! {
 		{
+			const _: () = core::assert!(#subscribers_count <= MAX_SUBSCRIBERS, "Too many subscribers, raise MAX_SUBSCRIBERS");
+
 			#[embassy_executor::task]
-			async fn subscriber_task(mut subscribers: [&'static mut dyn reactor::RSubscriber; #subscribers_count], mut middleware: [&'static mut dyn reactor::middleware::Middleware; #middleware_count]) {
+			async fn subscriber_task(subscribers: [&'static mut dyn reactor::RSubscriber; #subscribers_count], mut middleware: [&'static mut dyn reactor::middleware::Middleware; #middleware_count]) {
 				// Expects subscriber to be a global but that's fine?
 				let mut listener = #channel.subscriber().unwrap();
 				let publisher = #channel.publisher().unwrap();
 				info!("Subscriber task started for subscribers: {} and middleware: {}", stringify!(#subscribers), stringify!(#middleware));
 
-				loop {
-					let msg = listener.next_message_pure().await;
+				let queues: [reactor::queue::SubscriberQueue<'static, SUBSCRIBER_QUEUE_SIZE>; #subscribers_count] = core::array::from_fn(|i| {
+					reactor::queue::SubscriberQueue::new(subscribers[i].overflow_policy(), &SUBSCRIBER_DIAGNOSTICS[i])
+				});
+				// Shared between the receiver (for `is_supported`) and the deliveries (for `push`)
+				let subscribers = subscribers.map(core::cell::RefCell::new);
+
+				// Fan the events out to the subscriber queues
+				let receiver = async {
+					loop {
+						// Wake up for the earliest middleware deadline, if there's any
+						let deadline = middleware.iter().filter_map(|mid| mid.deadline()).min();
+						let next = match deadline {
+							Some(deadline) => embassy_futures::select::select(listener.next_message(), embassy_time::Timer::at(deadline)).await,
+							None => embassy_futures::select::Either::First(listener.next_message().await),
+						};
+
+						let result = match next {
+							embassy_futures::select::Either::First(result) => result,
+							embassy_futures::select::Either::Second(()) => {
+								let now = embassy_time::Instant::now();
+								for mid in &mut middleware {
+									if mid.deadline().is_some_and(|deadline| deadline <= now) {
+										let derived = mid.tick(now).await;
+										while let Some(msg) = mid.take_published() {
+											publisher.publish(msg).await;
+										}
+										if let Some(msg) = derived {
+											publisher.publish(msg).await;
+										}
+									}
+								}
+								continue;
+							},
+						};
+
+						let msg = match result {
+							embassy_sync::pubsub::WaitResult::Message(msg) => msg,
+							embassy_sync::pubsub::WaitResult::Lagged(count) => {
+								warn!("[subscriber] Lagged behind the channel by {} messages", count);
+								CHANNEL_DIAGNOSTICS.record_lagged(count as u32);
+								continue;
+							},
+						};
+
+						info!("[subscriber] Got a message: {:?}", msg);
+
+						for mid in &mut middleware {
+							let derived = mid.process(msg.clone()).await;
+							while let Some(msg) = mid.take_published() {
+								publisher.publish(msg).await;
+							}
+							if let Some(msg) = derived {
+								publisher.publish(msg).await;
+							}
+						}
 
-					info!("[subscriber] Got a message: {:?}", msg);
+						for (sub, queue) in subscribers.iter().zip(queues.iter()) {
+							// A subscriber busy pushing can't be asked - let its delivery filter instead
+							if let Ok(sub) = sub.try_borrow() {
+								if !sub.is_supported(msg.event) {
+									continue;
+								}
+							}
 
-					for mid in &mut middleware {
-						if let Some(msg) = mid.process(msg.clone()).await {
-							publisher.publish(msg).await;
+							queue.push(msg.clone()).await;
 						}
 					}
-
-					// TODO: Turn this into a join of all pollers
-					for sub in &mut subscribers {
-						if sub.is_supported(msg.clone()) {
-							sub.push(msg.clone()).await;
+				};
+
+				// Each subscriber drains its own queue, so a stalled one doesn't hold back the rest
+				let deliveries: [_; #subscribers_count] = core::array::from_fn(|i| {
+					let sub = &subscribers[i];
+					let queue = &queues[i];
+					async move {
+						loop {
+							let msg = queue.pop().await;
+							let mut sub = sub.borrow_mut();
+							if sub.is_supported(msg.event) {
+								sub.push(msg).await;
+								queue.diagnostics().record_delivered();
+							}
 						}
 					}
-				}
+				});
+
+				embassy_futures::join::join(receiver, embassy_futures::join::join_array(deliveries)).await;
 			}
 
 			subscriber_task(#subscribers, #middleware)
@@ -77,6 +156,37 @@ pub fn subscribers_task(input: TokenStream) -> TokenStream {
 	TokenStream::from(expanded)
 }
 
+#[proc_macro]
+pub fn publishers_task(input: TokenStream) -> TokenStream {
+	let publishers = syn::parse_macro_input!(input as ExprArray);
+	let publishers_count = publishers.elems.len();
+
+	let expanded = quote! {
+		{
+			#[embassy_executor::task]
+			async fn publisher_task(publishers: [&'static mut dyn reactor::Polled; #publishers_count]) {
+				info!("Publisher task started for publishers: {}", stringify!(#publishers));
+				reactor::scheduler::Scheduler::new(publishers).run().await;
+			}
+
+			publisher_task(#publishers)
+		}
+	};
+
+	TokenStream::from(expanded)
+}
+
+/// Generate the whole `main` of a board: the components listed in `global.publishers`,
+/// `global.middleware` and `global.subscribers` of the board config, the peripherals and tasks
+/// they need and the publishers/subscribers tasks wiring them together
+#[proc_macro]
+pub fn board_main(_input: TokenStream) -> TokenStream {
+	match board::expand() {
+		Ok(expanded) => TokenStream::from(expanded),
+		Err(e) => TokenStream::from(e.to_compile_error()),
+	}
+}
+
 struct SubscribersTaskEnvInput {
 	channel: Expr,
 	subscribers: LitStr,
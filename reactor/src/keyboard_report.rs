@@ -1,12 +1,12 @@
 use core::pin::Pin;
 
 use alloc::boxed::Box;
-use defmt::*;
 use futures::prelude::Future;
-use reactor::middleware::Middleware;
-use reactor::{KeyCode, KeyEvent, KeyModifiers, ReactorEvent};
-use usbd_hid::descriptor::KeyboardReport;
 
+use crate::middleware::Middleware;
+use crate::reactor_event::{EventEnvelope, KeyCode, KeyEvent, KeyModifiers, ReactorEvent};
+
+/// Keeps the pressed keys and modifiers, publishing them as a `KeyboardReport` on every change
 #[derive(Debug, Default)]
 pub struct KeyboardReportMid {
 	modifiers: KeyModifiers,
@@ -23,9 +23,9 @@ impl KeyboardReportMid {
 }
 
 impl Middleware for KeyboardReportMid {
-	fn process(&mut self, value: ReactorEvent) -> Pin<Box<dyn Future<Output = Option<ReactorEvent>> + '_>> {
+	fn process(&mut self, value: EventEnvelope) -> Pin<Box<dyn Future<Output = Option<EventEnvelope>> + '_>> {
 		Box::pin(async move {
-			match value {
+			match value.event {
 				ReactorEvent::Key(code) => match code {
 					KeyEvent::Pressed(key) => {
 						if key > KeyCode::LCtrl && key < KeyCode::RGui {
@@ -38,10 +38,9 @@ impl Middleware for KeyboardReportMid {
 							}
 						}
 
-						Some(self.into_event())
+						Some(value.derive(self.into_event()))
 					},
 					KeyEvent::Released(key) => {
-						info!("Released: {:?}", key);
 						if key > KeyCode::LCtrl && key < KeyCode::RGui {
 							self.modifiers = (<KeyModifiers as Into<u8>>::into(self.modifiers)
 								& (0 << (key as u8 - KeyCode::LCtrl as u8)))
@@ -50,32 +49,12 @@ impl Middleware for KeyboardReportMid {
 							self.keys[pos] = KeyCode::None;
 						}
 
-						Some(self.into_event())
+						Some(value.derive(self.into_event()))
 					},
+					KeyEvent::Held(_) => None,
 				},
 				_ => None,
 			}
 		})
 	}
 }
-
-impl Into<KeyboardReport> for KeyboardReportMid {
-	fn into(self) -> KeyboardReport {
-		// TODO: Make this a generic
-		let keycodes = [
-			self.keys[0] as u8,
-			self.keys[1] as u8,
-			self.keys[2] as u8,
-			self.keys[3] as u8,
-			self.keys[4] as u8,
-			self.keys[5] as u8,
-		];
-
-		KeyboardReport {
-			modifier: self.modifiers.into(),
-			reserved: 0,
-			leds: 0,
-			keycodes,
-		}
-	}
-}
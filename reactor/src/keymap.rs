@@ -0,0 +1,193 @@
+use core::pin::Pin;
+
+use alloc::boxed::Box;
+use alloc::collections::VecDeque;
+use alloc::vec;
+use alloc::vec::Vec;
+use embassy_time::{Duration, Instant};
+use futures::Future;
+
+use crate::middleware::Middleware;
+use crate::reactor_event::*;
+
+pub const KEYMAP_SOURCE: SourceId = SourceId(3);
+
+/// Turns the hardware mapped keys into keycodes and actions, layer by layer
+pub struct Keymap {
+	pub layers: Vec<Vec<Vec<KeyCodeInt>>>,
+	pub hold_time: Duration,
+	current_layer: usize,
+	/// Last state of each key and when it changed to it
+	last_state: Vec<Vec<(KeyEvent, Instant)>>,
+	/// Events made on top of the returned ones, waiting for `take_published`
+	published: VecDeque<EventEnvelope>,
+	/// What `OutputToggle` does - the keymap doesn't know about the transports
+	output_toggle: Option<fn()>,
+}
+
+impl Keymap {
+	pub fn new(keymap: Vec<Vec<Vec<KeyCodeInt>>>, hold_time: Duration) -> Self {
+		let last_state = vec![
+			vec![(KeyEvent::Released(KeyCode::None), Instant::from_ticks(0)); keymap[0][0].len()];
+			keymap[0].len()
+		];
+		Self {
+			layers: keymap,
+			hold_time,
+			last_state,
+			..Default::default()
+		}
+	}
+
+	pub fn with_output_toggle(self, output_toggle: fn()) -> Self {
+		Self {
+			output_toggle: Some(output_toggle),
+			..self
+		}
+	}
+}
+
+impl Default for Keymap {
+	fn default() -> Self {
+		let keymap = vec![vec![vec![]]];
+
+		Self {
+			layers: keymap,
+			hold_time: Duration::from_ticks(0),
+			last_state: vec![vec![(KeyEvent::Released(KeyCode::None), Instant::from_ticks(0))]],
+			current_layer: 0,
+			published: VecDeque::new(),
+			output_toggle: None,
+		}
+	}
+}
+
+// TODO: Specify the is_supported
+impl Middleware for Keymap {
+	fn process(&mut self, event: EventEnvelope) -> Pin<Box<dyn Future<Output = Option<EventEnvelope>> + '_>> {
+		Box::pin(async move {
+			let (value, rindex, cindex) = match event.event {
+				ReactorEvent::HardwareMappedBool(value, rindex, cindex) => (value, rindex, cindex),
+				_ => return None,
+			};
+			let active_keymap = &self.layers[self.current_layer];
+
+			let mut new_state: KeyEvent = self.last_state[rindex][cindex].0;
+
+			if let KeyCodeInt::Internal(internal) = active_keymap[rindex][cindex] {
+				let old_layer = self.current_layer;
+				match internal {
+					InternalEvent::LayerNext => {
+						self.current_layer += 1;
+						if self.current_layer >= self.layers.len() {
+							self.current_layer = 0;
+						}
+					},
+					InternalEvent::LayerPrev =>
+						if self.current_layer == 0 {
+							self.current_layer = self.layers.len() - 1;
+						} else {
+							self.current_layer -= 1;
+						},
+					InternalEvent::LayerChange(target) => {
+						self.current_layer = target;
+						if self.current_layer >= self.layers.len() {
+							self.current_layer = 0;
+						}
+					},
+					// Handled by the BLE subsystem and the tracer, once per press
+					InternalEvent::BLENext
+					| InternalEvent::BLEPrev
+					| InternalEvent::BLEChange(_)
+					| InternalEvent::BLEClear
+					| InternalEvent::BLEClearAll
+					| InternalEvent::TraceDump
+						if value =>
+						self.published.push_back(EventEnvelope::at(
+							ReactorEvent::Internal(internal),
+							KEYMAP_SOURCE,
+							event.timestamp,
+						)),
+					InternalEvent::OutputToggle if value =>
+						if let Some(output_toggle) = self.output_toggle {
+							output_toggle();
+						},
+					_ => {},
+				}
+
+				if old_layer != self.current_layer {
+					for row in self.last_state.iter_mut() {
+						for code in row.iter_mut() {
+							if let KeyEvent::Pressed(key) | KeyEvent::Held(key) = code.0 {
+								code.0 = KeyEvent::Released(key);
+								code.1 = event.timestamp;
+								self.published.push_back(EventEnvelope::at(
+									ReactorEvent::Key(KeyEvent::Released(key)),
+									KEYMAP_SOURCE,
+									event.timestamp,
+								));
+							}
+						}
+					}
+				}
+			} else if let KeyCodeInt::Key(key) = active_keymap[rindex][cindex] {
+				match self.last_state[rindex][cindex].0 {
+					KeyEvent::Released(_) =>
+						if value {
+							new_state = KeyEvent::Pressed(key);
+						},
+					KeyEvent::Pressed(_) | KeyEvent::Held(_) =>
+						if !value {
+							new_state = KeyEvent::Released(key)
+						},
+				};
+			}
+
+			if new_state != self.last_state[rindex][cindex].0 {
+				self.last_state[rindex][cindex] = (new_state, event.timestamp);
+				return Some(EventEnvelope::at(
+					ReactorEvent::Key(new_state),
+					KEYMAP_SOURCE,
+					event.timestamp,
+				));
+			}
+
+			None
+		})
+	}
+
+	fn deadline(&self) -> Option<Instant> {
+		if self.hold_time.as_ticks() == 0 {
+			return None;
+		}
+
+		self.last_state
+			.iter()
+			.flatten()
+			.filter(|(state, _)| matches!(state, KeyEvent::Pressed(_)))
+			.map(|(_, since)| *since + self.hold_time)
+			.min()
+	}
+
+	fn tick(&mut self, now: Instant) -> Pin<Box<dyn Future<Output = Option<EventEnvelope>> + '_>> {
+		Box::pin(async move {
+			for row in self.last_state.iter_mut() {
+				for (state, since) in row.iter_mut() {
+					if let KeyEvent::Pressed(key) = *state {
+						if *since + self.hold_time <= now {
+							*state = KeyEvent::Held(key);
+							self.published
+								.push_back(EventEnvelope::at(ReactorEvent::Key(*state), KEYMAP_SOURCE, now));
+						}
+					}
+				}
+			}
+
+			None
+		})
+	}
+
+	fn take_published(&mut self) -> Option<EventEnvelope> {
+		self.published.pop_front()
+	}
+}
@@ -5,13 +5,23 @@ extern crate alloc;
 use core::pin::Pin;
 
 use alloc::boxed::Box;
+use embassy_time::Duration;
 use futures::Future;
 
+pub mod keyboard_report;
+pub mod keymap;
 pub mod middleware;
+pub mod queue;
 pub mod reactor_event;
+pub mod scheduler;
+pub mod trace;
 
+use crate::queue::OverflowPolicy;
 pub use crate::reactor_event::*;
 
+/// Period used by publishers that don't pick their own
+pub const DEFAULT_POLL_PERIOD: Duration = Duration::from_millis(2);
+
 pub trait RPublisher {}
 
 pub trait Interrupted: RPublisher {
@@ -21,6 +31,10 @@ pub trait Interrupted: RPublisher {
 
 pub trait Polled: RPublisher {
 	fn poll(&mut self) -> Pin<Box<dyn Future<Output = ()> + '_>>;
+	/// Time between two polls - asked again after every poll
+	fn period(&self) -> Duration {
+		DEFAULT_POLL_PERIOD
+	}
 }
 
 impl<T: Polled> Interrupted for T {
@@ -32,9 +46,13 @@ impl<T: Polled> Interrupted for T {
 pub trait RSubscriber {
 	// TODO: Keep the type and add an event `Any` to the enum or let the subscriber define the whole logic?
 	// type SupportedEvents: IntoIterator<Item = ReactorEvent>;
-	fn push(&mut self, value: ReactorEvent) -> Pin<Box<dyn Future<Output = ()> + '_>>;
+	fn push(&mut self, value: EventEnvelope) -> Pin<Box<dyn Future<Output = ()> + '_>>;
 	fn is_supported(&self, _event: ReactorEvent) -> bool {
 		// Self::SupportedEvents::into_iter().any(|e| e == event)
 		true
 	}
+	/// How the subscriber's queue behaves when the subscriber can't keep up
+	fn overflow_policy(&self) -> OverflowPolicy {
+		OverflowPolicy::default()
+	}
 }
@@ -1,17 +1,34 @@
 use core::pin::Pin;
 
 use alloc::boxed::Box;
+use embassy_time::Instant;
 use futures::Future;
 
-use crate::reactor_event::ReactorEvent;
+use crate::reactor_event::EventEnvelope;
 use crate::RSubscriber;
 
 pub trait Middleware {
-	fn process(&mut self, value: ReactorEvent) -> Pin<Box<dyn Future<Output = Option<ReactorEvent>> + '_>>;
+	fn process(&mut self, value: EventEnvelope) -> Pin<Box<dyn Future<Output = Option<EventEnvelope>> + '_>>;
+	/// Next time the middleware wants to be woken up through `tick`, even if no event arrives
+	///
+	/// Asked again after every `process` and `tick`, so returning a new instant re-registers the deadline
+	fn deadline(&self) -> Option<Instant> {
+		None
+	}
+	/// Called once the `deadline` has passed
+	fn tick(&mut self, _now: Instant) -> Pin<Box<dyn Future<Output = Option<EventEnvelope>> + '_>> {
+		Box::pin(async { None })
+	}
+	/// Events made besides the one `process` or `tick` returned, e.g. the releases of a layer change
+	///
+	/// Taken until `None` after every `process` and `tick`, and published before the returned event
+	fn take_published(&mut self) -> Option<EventEnvelope> {
+		None
+	}
 }
 
 impl<T: Middleware> RSubscriber for T {
-	fn push(&mut self, value: ReactorEvent) -> Pin<Box<dyn futures::Future<Output = ()> + '_>> {
+	fn push(&mut self, value: EventEnvelope) -> Pin<Box<dyn futures::Future<Output = ()> + '_>> {
 		Box::pin(async move {
 			self.process(value).await;
 		})
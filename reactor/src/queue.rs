@@ -0,0 +1,272 @@
+use core::cell::RefCell;
+use core::mem::discriminant;
+use core::sync::atomic::{AtomicU32, Ordering};
+
+use defmt::Format;
+use embassy_sync::blocking_mutex::raw::NoopRawMutex;
+use embassy_sync::signal::Signal;
+use heapless::Deque;
+
+use crate::reactor_event::EventEnvelope;
+
+/// What a subscriber queue does when a new event arrives while it's full
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Format)]
+pub enum OverflowPolicy {
+	/// Discard the oldest queued event to make room for the new one
+	DropOldest,
+	/// Replace the latest queued event of the same kind (e.g. an older report) with the new one,
+	/// falling back to `DropOldest` if there's nothing to coalesce with
+	CoalesceLatest,
+	/// Wait until the subscriber catches up - stalls delivery to everyone else
+	#[default]
+	Block,
+}
+
+/// Delivery counters of a single subscriber (or of the channel listener)
+pub struct Diagnostics {
+	delivered: AtomicU32,
+	dropped: AtomicU32,
+	coalesced: AtomicU32,
+	lagged: AtomicU32,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Format)]
+pub struct DiagnosticsSnapshot {
+	pub delivered: u32,
+	pub dropped: u32,
+	pub coalesced: u32,
+	pub lagged: u32,
+}
+
+impl Diagnostics {
+	pub const fn new() -> Self {
+		Self {
+			delivered: AtomicU32::new(0),
+			dropped: AtomicU32::new(0),
+			coalesced: AtomicU32::new(0),
+			lagged: AtomicU32::new(0),
+		}
+	}
+
+	pub fn record_delivered(&self) {
+		self.delivered.fetch_add(1, Ordering::Relaxed);
+	}
+
+	pub fn record_dropped(&self) {
+		self.dropped.fetch_add(1, Ordering::Relaxed);
+	}
+
+	pub fn record_coalesced(&self) {
+		self.coalesced.fetch_add(1, Ordering::Relaxed);
+	}
+
+	pub fn record_lagged(&self, count: u32) {
+		self.lagged.fetch_add(count, Ordering::Relaxed);
+	}
+
+	pub fn snapshot(&self) -> DiagnosticsSnapshot {
+		DiagnosticsSnapshot {
+			delivered: self.delivered.load(Ordering::Relaxed),
+			dropped: self.dropped.load(Ordering::Relaxed),
+			coalesced: self.coalesced.load(Ordering::Relaxed),
+			lagged: self.lagged.load(Ordering::Relaxed),
+		}
+	}
+}
+
+impl Default for Diagnostics {
+	fn default() -> Self {
+		Self::new()
+	}
+}
+
+/// Bounded per-subscriber event queue
+///
+/// Meant to be shared between the future that reads the channel and the future that feeds
+/// the subscriber, both running inside the same task - hence no real mutex
+pub struct SubscriberQueue<'a, const N: usize> {
+	events: RefCell<Deque<EventEnvelope, N>>,
+	policy: OverflowPolicy,
+	diagnostics: &'a Diagnostics,
+	ready: Signal<NoopRawMutex, ()>,
+	space: Signal<NoopRawMutex, ()>,
+}
+
+impl<'a, const N: usize> SubscriberQueue<'a, N> {
+	pub fn new(policy: OverflowPolicy, diagnostics: &'a Diagnostics) -> Self {
+		Self {
+			events: RefCell::new(Deque::new()),
+			policy,
+			diagnostics,
+			ready: Signal::new(),
+			space: Signal::new(),
+		}
+	}
+
+	pub fn policy(&self) -> OverflowPolicy {
+		self.policy
+	}
+
+	pub fn diagnostics(&self) -> &Diagnostics {
+		self.diagnostics
+	}
+
+	/// Enqueue an event, applying the overflow policy if the queue is full
+	pub async fn push(&self, event: EventEnvelope) {
+		loop {
+			if self.try_push(event) {
+				self.ready.signal(());
+				return;
+			}
+
+			// Only reachable with `OverflowPolicy::Block`
+			self.space.wait().await;
+		}
+	}
+
+	fn try_push(&self, event: EventEnvelope) -> bool {
+		let mut events = self.events.borrow_mut();
+
+		if events.is_full() && self.policy == OverflowPolicy::CoalesceLatest {
+			if let Some(last) = events.back_mut() {
+				if discriminant(&last.event) == discriminant(&event.event) {
+					*last = event;
+					self.diagnostics.record_coalesced();
+					return true;
+				}
+			}
+		}
+
+		if events.is_full() {
+			if self.policy == OverflowPolicy::Block {
+				return false;
+			}
+
+			events.pop_front();
+			self.diagnostics.record_dropped();
+		}
+
+		// Can't fail, we made room above
+		let _ = events.push_back(event);
+		true
+	}
+
+	/// Wait for the next event of the queue
+	pub async fn pop(&self) -> EventEnvelope {
+		loop {
+			if let Some(event) = self.events.borrow_mut().pop_front() {
+				self.space.signal(());
+				return event;
+			}
+
+			self.ready.wait().await;
+		}
+	}
+
+	pub fn len(&self) -> usize {
+		self.events.borrow().len()
+	}
+
+	pub fn is_empty(&self) -> bool {
+		self.events.borrow().is_empty()
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use alloc::vec::Vec;
+
+	use embassy_futures::block_on;
+	use embassy_time::Instant;
+
+	use super::*;
+	use crate::reactor_event::{ReactorEvent, SourceId};
+
+	fn battery(percent: u8) -> EventEnvelope {
+		EventEnvelope::at(ReactorEvent::Battery(percent), SourceId(0), Instant::from_ticks(0))
+	}
+
+	fn led(on: bool) -> EventEnvelope {
+		EventEnvelope::at(ReactorEvent::LED(on), SourceId(0), Instant::from_ticks(0))
+	}
+
+	fn drain<const N: usize>(queue: &SubscriberQueue<'_, N>) -> Vec<ReactorEvent> {
+		let mut events = Vec::new();
+		while !queue.is_empty() {
+			events.push(block_on(queue.pop()).event);
+		}
+		events
+	}
+
+	#[test]
+	fn drop_oldest_makes_room() {
+		let diagnostics = Diagnostics::new();
+		let queue = SubscriberQueue::<2>::new(OverflowPolicy::DropOldest, &diagnostics);
+
+		for percent in 1..=3 {
+			assert!(queue.try_push(battery(percent)));
+		}
+
+		assert_eq!(drain(&queue), [ReactorEvent::Battery(2), ReactorEvent::Battery(3)]);
+		assert_eq!(diagnostics.snapshot().dropped, 1);
+	}
+
+	#[test]
+	fn coalesce_latest_keeps_events_while_there_is_room() {
+		let diagnostics = Diagnostics::new();
+		let queue = SubscriberQueue::<3>::new(OverflowPolicy::CoalesceLatest, &diagnostics);
+
+		assert!(queue.try_push(battery(1)));
+		assert!(queue.try_push(battery(2)));
+
+		assert_eq!(drain(&queue), [ReactorEvent::Battery(1), ReactorEvent::Battery(2)]);
+		assert_eq!(diagnostics.snapshot().coalesced, 0);
+	}
+
+	#[test]
+	fn coalesce_latest_replaces_the_same_kind_once_full() {
+		let diagnostics = Diagnostics::new();
+		let queue = SubscriberQueue::<2>::new(OverflowPolicy::CoalesceLatest, &diagnostics);
+
+		assert!(queue.try_push(led(true)));
+		assert!(queue.try_push(battery(1)));
+		assert!(queue.try_push(battery(2)));
+
+		assert_eq!(drain(&queue), [ReactorEvent::LED(true), ReactorEvent::Battery(2)]);
+		let snapshot = diagnostics.snapshot();
+		assert_eq!((snapshot.coalesced, snapshot.dropped), (1, 0));
+	}
+
+	#[test]
+	fn coalesce_latest_falls_back_to_drop_oldest() {
+		let diagnostics = Diagnostics::new();
+		let queue = SubscriberQueue::<2>::new(OverflowPolicy::CoalesceLatest, &diagnostics);
+
+		assert!(queue.try_push(battery(1)));
+		assert!(queue.try_push(battery(2)));
+		assert!(queue.try_push(led(true)));
+
+		assert_eq!(drain(&queue), [ReactorEvent::Battery(2), ReactorEvent::LED(true)]);
+		let snapshot = diagnostics.snapshot();
+		assert_eq!((snapshot.coalesced, snapshot.dropped), (0, 1));
+	}
+
+	#[test]
+	fn block_refuses_until_popped() {
+		let diagnostics = Diagnostics::new();
+		let queue = SubscriberQueue::<1>::new(OverflowPolicy::Block, &diagnostics);
+
+		assert!(queue.try_push(battery(1)));
+		assert!(!queue.try_push(battery(2)));
+		assert_eq!(queue.len(), 1);
+
+		assert_eq!(block_on(queue.pop()).event, ReactorEvent::Battery(1));
+		assert!(queue.try_push(battery(2)));
+		assert_eq!(diagnostics.snapshot().dropped, 0);
+	}
+
+	#[test]
+	fn default_policy_blocks() {
+		assert_eq!(OverflowPolicy::default(), OverflowPolicy::Block);
+	}
+}
@@ -1,12 +1,16 @@
+use core::str::FromStr;
+
 use defmt::Format;
-use strum::EnumString;
+use embassy_time::Instant;
+use strum::{EnumString, ParseError};
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Format, EnumString)]
 pub enum KeyEvent {
 	Pressed(KeyCode),
 	Released(KeyCode),
+	/// Still pressed after the hold time - always followed by a `Released`
+	Held(KeyCode),
 	// TODO: Configurable alternate button behavior
-	// Held(KeyCode),
 	// DoublePressed(KeyCode),
 }
 
@@ -26,6 +30,16 @@ pub enum InternalEvent {
 	BLENext,
 	BLEPrev,
 	BLEChange(usize),
+	/// Forget the host of the current BLE slot
+	BLEClear,
+	/// Forget the hosts of all the BLE slots
+	BLEClearAll,
+
+	/// Switch the preferred output between USB and BLE
+	OutputToggle,
+
+	/// Log the event trace over defmt
+	TraceDump,
 }
 
 impl Default for InternalEvent {
@@ -54,6 +68,8 @@ pub enum ReactorEvent {
 		x: u32,
 		y: u32,
 	},
+	// Buttons held, one bit each from the left one, like in a boot mouse report
+	MouseButtons(u8),
 
 	Potentiometer {
 		v: i16,
@@ -91,6 +107,17 @@ pub enum ReactorEvent {
 	// TODO: LED strip
 	// TODO: Screen (widgets?)
 
+	// Actions of the keymap for other components, e.g. BLE slot switching
+	Internal(InternalEvent),
+
+	// Passkey to show while pairing over BLE, `None` once pairing is over
+	Passkey(Option<u32>),
+	// Transports the reports go out on
+	Output {
+		usb: bool,
+		ble: bool,
+	},
+
 	// Hardware
 	// TODO: Why 2 dimensions? Why not 1? Why not variable?
 	HardwareMappedBool(bool, usize, usize),
@@ -99,6 +126,59 @@ pub enum ReactorEvent {
 	Analog6Axis(i16, i16, i16, i16, i16, i16),
 }
 
+impl ReactorEvent {
+	/// `Locks` of the LED output report of a HID keyboard
+	pub fn from_leds(leds: u8) -> Self {
+		Self::Locks {
+			num: leds & 1 != 0,
+			caps: leds & 1 << 1 != 0,
+			scroll: leds & 1 << 2 != 0,
+		}
+	}
+}
+
+/// Identifies the component that produced an event
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Format)]
+pub struct SourceId(pub u8);
+
+impl SourceId {
+	pub const UNKNOWN: Self = Self(0);
+}
+
+impl Default for SourceId {
+	fn default() -> Self {
+		Self::UNKNOWN
+	}
+}
+
+/// A `ReactorEvent` along with when and where it happened
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Format)]
+pub struct EventEnvelope {
+	pub event: ReactorEvent,
+	pub timestamp: Instant,
+	pub source: SourceId,
+}
+
+impl EventEnvelope {
+	/// Wrap an event that happened just now
+	pub fn new(event: ReactorEvent, source: SourceId) -> Self {
+		Self::at(event, source, Instant::now())
+	}
+
+	pub fn at(event: ReactorEvent, source: SourceId, timestamp: Instant) -> Self {
+		Self {
+			event,
+			timestamp,
+			source,
+		}
+	}
+
+	/// Wrap an event produced out of this one, keeping its origin and timing
+	pub fn derive(&self, event: ReactorEvent) -> Self {
+		Self { event, ..*self }
+	}
+}
+
 #[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Format, EnumString)]
 #[repr(u8)]
 pub enum KeyCode {
@@ -363,23 +443,60 @@ impl Into<u8> for KeyCode {
 	}
 }
 
+/// `None` for anything that isn't a keycode, see `from_u8` to tell them apart
 impl From<u8> for KeyCode {
 	fn from(value: u8) -> Self {
-		if value > 0xFB {
-			Self::None
-		} else {
-			unsafe { core::mem::transmute(value) }
+		Self::from_u8(value).unwrap_or(Self::None)
+	}
+}
+
+// The ranges `from_u8` checks against
+const _: () = assert!(KeyCode::ExSel as u8 == 0xA4 && KeyCode::LCtrl as u8 == 0xE0 && KeyCode::MediaCalc as u8 == 0xFB);
+
+impl KeyCode {
+	/// The keycode of `value`, if there's one - the variants stop at `ExSel` and start again at `LCtrl`
+	pub fn from_u8(value: u8) -> Option<Self> {
+		match value {
+			// Every value of these ranges is a variant, see the assertion above
+			0x00..=0xA4 | 0xE0..=0xFB => Some(unsafe { core::mem::transmute::<u8, KeyCode>(value) }),
+			_ => None,
 		}
 	}
 }
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq, Format, EnumString)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Format)]
 pub enum KeyCodeInt {
 	None,
 	Key(KeyCode),
 	Internal(InternalEvent),
 }
 
+/// A key of a keymap, either a keycode (`A`) or an internal event with its argument in parentheses
+/// when it takes one (`LayerChange(1)`)
+impl FromStr for KeyCodeInt {
+	type Err = ParseError;
+
+	fn from_str(s: &str) -> Result<Self, Self::Err> {
+		if let Ok(code) = KeyCode::from_str(s) {
+			return Ok(Self::Key(code));
+		}
+
+		let (name, arg) = match s.strip_suffix(')').and_then(|s| s.split_once('(')) {
+			Some((name, arg)) => (name, Some(arg.parse().map_err(|_| ParseError::VariantNotFound)?)),
+			None => (s, None),
+		};
+		let event = match (InternalEvent::from_str(name)?, arg) {
+			(InternalEvent::LayerChange(_), Some(target)) => InternalEvent::LayerChange(target),
+			(InternalEvent::BLEChange(_), Some(target)) => InternalEvent::BLEChange(target),
+			(InternalEvent::LayerChange(_) | InternalEvent::BLEChange(_), None) | (_, Some(_)) =>
+				return Err(ParseError::VariantNotFound),
+			(event, None) => event,
+		};
+
+		Ok(Self::Internal(event))
+	}
+}
+
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Format)]
 pub struct KeyModifiers {
 	pub lctrl: bool,
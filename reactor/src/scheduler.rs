@@ -0,0 +1,34 @@
+use embassy_futures::join::join_array;
+use embassy_time::{Instant, Timer};
+
+use crate::Polled;
+
+/// Polls multiple publishers from a single task, each one at its own period
+///
+/// The period is asked again after every poll, so a publisher can slow itself down
+/// (e.g. when idle) or speed back up just by returning a different `Polled::period`
+pub struct Scheduler<'a, const N: usize> {
+	publishers: [&'a mut dyn Polled; N],
+}
+
+impl<'a, const N: usize> Scheduler<'a, N> {
+	pub fn new(publishers: [&'a mut dyn Polled; N]) -> Self {
+		Self { publishers }
+	}
+
+	pub async fn run(self) {
+		let pollers = self.publishers.map(|publisher| async move {
+			let mut next = Instant::now();
+
+			loop {
+				publisher.poll().await;
+
+				// Don't try to catch up if a poll took longer than the period
+				next = (next + publisher.period()).max(Instant::now());
+				Timer::at(next).await;
+			}
+		});
+
+		join_array(pollers).await;
+	}
+}
@@ -0,0 +1,522 @@
+use alloc::collections::VecDeque;
+use alloc::vec::Vec;
+use embassy_time::{Duration, Instant};
+use heapless::HistoryBuffer;
+
+use crate::middleware::Middleware;
+use crate::reactor_event::*;
+
+/// Size of the record header: timestamp in ms (u32), source (u8) and event tag (u8)
+pub const RECORD_HEADER_SIZE: usize = 6;
+/// Largest encoded record, a `Joystick6DoF`/`Analog6Axis` event
+pub const RECORD_SIZE_MAX: usize = RECORD_HEADER_SIZE + 12;
+
+/// Keeps the last `N` events that went through the channel
+pub struct TraceBuffer<const N: usize> {
+	records: HistoryBuffer<EventEnvelope, N>,
+	total: u32,
+}
+
+impl<const N: usize> TraceBuffer<N> {
+	pub const fn new() -> Self {
+		Self {
+			records: HistoryBuffer::new(),
+			total: 0,
+		}
+	}
+
+	pub fn record(&mut self, event: EventEnvelope) {
+		self.records.write(event);
+		self.total = self.total.wrapping_add(1);
+	}
+
+	/// Recorded events, oldest first
+	pub fn iter(&self) -> impl Iterator<Item = &EventEnvelope> {
+		self.records.oldest_ordered()
+	}
+
+	pub fn len(&self) -> usize {
+		self.records.len()
+	}
+
+	pub fn is_empty(&self) -> bool {
+		self.records.len() == 0
+	}
+
+	/// Events that didn't fit and got overwritten since the last `clear`
+	pub fn overwritten(&self) -> u32 {
+		self.total.saturating_sub(self.records.len() as u32)
+	}
+
+	pub fn clear(&mut self) {
+		self.records.clear();
+		self.total = 0;
+	}
+
+	/// Encode all the recorded events back to back, oldest first
+	pub fn encode(&self, mut write: impl FnMut(&[u8])) {
+		let mut buf = [0u8; RECORD_SIZE_MAX];
+		for event in self.iter() {
+			let len = encode(event, &mut buf);
+			write(&buf[..len]);
+		}
+	}
+}
+
+impl<const N: usize> Default for TraceBuffer<N> {
+	fn default() -> Self {
+		Self::new()
+	}
+}
+
+struct Writer<'a> {
+	buf: &'a mut [u8],
+	pos: usize,
+}
+
+impl<'a> Writer<'a> {
+	fn u8(&mut self, value: u8) {
+		self.buf[self.pos] = value;
+		self.pos += 1;
+	}
+
+	fn u16(&mut self, value: u16) {
+		self.buf[self.pos..self.pos + 2].copy_from_slice(&value.to_le_bytes());
+		self.pos += 2;
+	}
+
+	fn u32(&mut self, value: u32) {
+		self.buf[self.pos..self.pos + 4].copy_from_slice(&value.to_le_bytes());
+		self.pos += 4;
+	}
+
+	fn i16(&mut self, value: i16) {
+		self.u16(value as u16);
+	}
+}
+
+struct Reader<'a> {
+	buf: &'a [u8],
+	pos: usize,
+}
+
+impl<'a> Reader<'a> {
+	fn u8(&mut self) -> Option<u8> {
+		let value = *self.buf.get(self.pos)?;
+		self.pos += 1;
+		Some(value)
+	}
+
+	fn u16(&mut self) -> Option<u16> {
+		let bytes = self.buf.get(self.pos..self.pos + 2)?;
+		self.pos += 2;
+		Some(u16::from_le_bytes([bytes[0], bytes[1]]))
+	}
+
+	fn u32(&mut self) -> Option<u32> {
+		let bytes = self.buf.get(self.pos..self.pos + 4)?;
+		self.pos += 4;
+		Some(u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+	}
+
+	fn i16(&mut self) -> Option<i16> {
+		self.u16().map(|value| value as i16)
+	}
+}
+
+/// Encode a record into `buf` (at least `RECORD_SIZE_MAX` long), returning the bytes used
+///
+/// Timestamps are stored in ms so captures don't depend on the tick rate of the device
+pub fn encode(record: &EventEnvelope, buf: &mut [u8]) -> usize {
+	let mut w = Writer { buf, pos: 0 };
+	w.u32(record.timestamp.as_millis() as u32);
+	w.u8(record.source.0);
+
+	match record.event {
+		ReactorEvent::Key(key) => {
+			w.u8(0);
+			let (kind, code) = match key {
+				KeyEvent::Pressed(code) => (0, code),
+				KeyEvent::Released(code) => (1, code),
+				KeyEvent::Held(code) => (2, code),
+			};
+			w.u8(kind);
+			w.u8(code.into());
+		},
+		ReactorEvent::Locks { caps, num, scroll } => {
+			w.u8(1);
+			w.u8((num as u8) | (caps as u8) << 1 | (scroll as u8) << 2);
+		},
+		ReactorEvent::KeyboardReport { modifier, keycodes } => {
+			w.u8(2);
+			w.u8(modifier.into());
+			for code in keycodes {
+				w.u8(code.into());
+			}
+		},
+		ReactorEvent::Mouse { x, y } => {
+			w.u8(3);
+			w.u32(x);
+			w.u32(y);
+		},
+		ReactorEvent::Potentiometer { v } => {
+			w.u8(4);
+			w.i16(v);
+		},
+		ReactorEvent::Joystick { x, y } => {
+			w.u8(5);
+			w.i16(x);
+			w.i16(y);
+		},
+		ReactorEvent::FullJoystick { x, y, z } => {
+			w.u8(6);
+			w.i16(x);
+			w.i16(y);
+			w.i16(z);
+		},
+		ReactorEvent::Joystick6DoF { x, y, z, rx, ry, rz } => {
+			w.u8(7);
+			for v in [x, y, z, rx, ry, rz] {
+				w.i16(v);
+			}
+		},
+		ReactorEvent::Battery(level) => {
+			w.u8(8);
+			w.u8(level);
+		},
+		ReactorEvent::LED(on) => {
+			w.u8(9);
+			w.u8(on as u8);
+		},
+		ReactorEvent::LEDAnalog(value) => {
+			w.u8(10);
+			w.u8(value);
+		},
+		ReactorEvent::RGBLED { r, g, b } => {
+			w.u8(11);
+			w.u8(r);
+			w.u8(g);
+			w.u8(b);
+		},
+		ReactorEvent::HardwareMappedBool(value, row, col) => {
+			w.u8(12);
+			w.u8(value as u8);
+			w.u16(row as u16);
+			w.u16(col as u16);
+		},
+		ReactorEvent::HardwareMappedU8(value, row, col) => {
+			w.u8(13);
+			w.u8(value);
+			w.u16(row as u16);
+			w.u16(col as u16);
+		},
+		ReactorEvent::HardwareMappedU16(value, row, col) => {
+			w.u8(14);
+			w.u16(value);
+			w.u16(row as u16);
+			w.u16(col as u16);
+		},
+		ReactorEvent::Analog6Axis(a, b, c, d, e, f) => {
+			w.u8(15);
+			for v in [a, b, c, d, e, f] {
+				w.i16(v);
+			}
+		},
+		ReactorEvent::Internal(event) => {
+			w.u8(16);
+			let (kind, arg) = match event {
+				InternalEvent::None => (0, 0),
+				InternalEvent::LayerNext => (1, 0),
+				InternalEvent::LayerPrev => (2, 0),
+				InternalEvent::LayerChange(target) => (3, target),
+				InternalEvent::BLENext => (4, 0),
+				InternalEvent::BLEPrev => (5, 0),
+				InternalEvent::BLEChange(target) => (6, target),
+				InternalEvent::BLEClear => (7, 0),
+				InternalEvent::BLEClearAll => (8, 0),
+				InternalEvent::OutputToggle => (9, 0),
+				InternalEvent::TraceDump => (10, 0),
+			};
+			w.u8(kind);
+			w.u16(arg as u16);
+		},
+		ReactorEvent::Passkey(passkey) => {
+			w.u8(17);
+			w.u8(passkey.is_some() as u8);
+			w.u32(passkey.unwrap_or_default());
+		},
+		ReactorEvent::Output { usb, ble } => {
+			w.u8(18);
+			w.u8(usb as u8);
+			w.u8(ble as u8);
+		},
+		ReactorEvent::MouseButtons(buttons) => {
+			w.u8(19);
+			w.u8(buttons);
+		},
+	}
+
+	w.pos
+}
+
+/// Decode a record from the start of `buf`, returning it along with the bytes it took
+pub fn decode(buf: &[u8]) -> Option<(EventEnvelope, usize)> {
+	let mut r = Reader { buf, pos: 0 };
+	let timestamp = Instant::from_millis(r.u32()? as u64);
+	let source = SourceId(r.u8()?);
+
+	let event = match r.u8()? {
+		0 => {
+			let kind = r.u8()?;
+			// Captures come from outside, anything but a keycode makes the record malformed
+			let code = KeyCode::from_u8(r.u8()?)?;
+			ReactorEvent::Key(match kind {
+				0 => KeyEvent::Pressed(code),
+				1 => KeyEvent::Released(code),
+				2 => KeyEvent::Held(code),
+				_ => return None,
+			})
+		},
+		1 => {
+			let locks = r.u8()?;
+			ReactorEvent::Locks {
+				num: locks & 1 != 0,
+				caps: locks & 1 << 1 != 0,
+				scroll: locks & 1 << 2 != 0,
+			}
+		},
+		2 => {
+			let modifier = KeyModifiers::from(r.u8()?);
+			let mut keycodes = [KeyCode::None; 6];
+			for code in keycodes.iter_mut() {
+				*code = KeyCode::from_u8(r.u8()?)?;
+			}
+			ReactorEvent::KeyboardReport { modifier, keycodes }
+		},
+		3 => ReactorEvent::Mouse {
+			x: r.u32()?,
+			y: r.u32()?,
+		},
+		4 => ReactorEvent::Potentiometer { v: r.i16()? },
+		5 => ReactorEvent::Joystick {
+			x: r.i16()?,
+			y: r.i16()?,
+		},
+		6 => ReactorEvent::FullJoystick {
+			x: r.i16()?,
+			y: r.i16()?,
+			z: r.i16()?,
+		},
+		7 => ReactorEvent::Joystick6DoF {
+			x: r.i16()?,
+			y: r.i16()?,
+			z: r.i16()?,
+			rx: r.i16()?,
+			ry: r.i16()?,
+			rz: r.i16()?,
+		},
+		8 => ReactorEvent::Battery(r.u8()?),
+		9 => ReactorEvent::LED(r.u8()? != 0),
+		10 => ReactorEvent::LEDAnalog(r.u8()?),
+		11 => ReactorEvent::RGBLED {
+			r: r.u8()?,
+			g: r.u8()?,
+			b: r.u8()?,
+		},
+		12 => ReactorEvent::HardwareMappedBool(r.u8()? != 0, r.u16()? as usize, r.u16()? as usize),
+		13 => ReactorEvent::HardwareMappedU8(r.u8()?, r.u16()? as usize, r.u16()? as usize),
+		14 => ReactorEvent::HardwareMappedU16(r.u16()?, r.u16()? as usize, r.u16()? as usize),
+		15 => ReactorEvent::Analog6Axis(r.i16()?, r.i16()?, r.i16()?, r.i16()?, r.i16()?, r.i16()?),
+		16 => {
+			let kind = r.u8()?;
+			let arg = r.u16()? as usize;
+			ReactorEvent::Internal(match kind {
+				0 => InternalEvent::None,
+				1 => InternalEvent::LayerNext,
+				2 => InternalEvent::LayerPrev,
+				3 => InternalEvent::LayerChange(arg),
+				4 => InternalEvent::BLENext,
+				5 => InternalEvent::BLEPrev,
+				6 => InternalEvent::BLEChange(arg),
+				7 => InternalEvent::BLEClear,
+				8 => InternalEvent::BLEClearAll,
+				9 => InternalEvent::OutputToggle,
+				10 => InternalEvent::TraceDump,
+				_ => return None,
+			})
+		},
+		17 => {
+			let shown = r.u8()? != 0;
+			let passkey = r.u32()?;
+			ReactorEvent::Passkey(shown.then_some(passkey))
+		},
+		18 => ReactorEvent::Output {
+			usb: r.u8()? != 0,
+			ble: r.u8()? != 0,
+		},
+		19 => ReactorEvent::MouseButtons(r.u8()?),
+		_ => return None,
+	};
+
+	Some((EventEnvelope::at(event, source, timestamp), r.pos))
+}
+
+/// Decode a whole capture, stopping at the first malformed record
+pub fn decode_all(mut buf: &[u8]) -> Vec<EventEnvelope> {
+	let mut records = Vec::new();
+	while let Some((record, len)) = decode(buf) {
+		records.push(record);
+		buf = &buf[len..];
+	}
+	records
+}
+
+/// Feed a capture through middleware on a virtual clock, returning everything they produced
+///
+/// What a middleware makes goes through all of them again, like it would through the channel.
+/// Deadlines fire at the exact instant they were asked for instead of whenever the executor
+/// gets to them, so the same capture always gives the same output
+pub async fn replay(
+	records: impl IntoIterator<Item = EventEnvelope>,
+	middleware: &mut [&mut dyn Middleware],
+) -> Vec<EventEnvelope> {
+	let mut output = Vec::new();
+
+	for record in records {
+		tick_until(record.timestamp, middleware, &mut output).await;
+		propagate(VecDeque::from([record]), middleware, &mut output).await;
+	}
+
+	// Let pending timers (e.g. a key still held at the end of the capture) play out
+	tick_until(Instant::MAX - Duration::from_ticks(1), middleware, &mut output).await;
+
+	output
+}
+
+async fn tick_until(until: Instant, middleware: &mut [&mut dyn Middleware], output: &mut Vec<EventEnvelope>) {
+	let mut last = None;
+
+	loop {
+		let next = middleware
+			.iter()
+			.filter_map(|mid| mid.deadline())
+			.filter(|deadline| *deadline <= until)
+			.min();
+
+		// Also bail on a middleware that keeps asking for the same deadline after its tick
+		let Some(now) = next.filter(|now| last != Some(*now)) else {
+			return;
+		};
+		last = Some(now);
+
+		let mut pending = VecDeque::new();
+		for mid in middleware.iter_mut() {
+			if mid.deadline().is_some_and(|deadline| deadline <= now) {
+				let derived = mid.tick(now).await;
+				take_made(&mut **mid, derived, &mut pending, output);
+			}
+		}
+
+		propagate(pending, middleware, output).await;
+	}
+}
+
+/// Run events through every middleware, then what they made out of them
+async fn propagate(
+	mut pending: VecDeque<EventEnvelope>,
+	middleware: &mut [&mut dyn Middleware],
+	output: &mut Vec<EventEnvelope>,
+) {
+	while let Some(event) = pending.pop_front() {
+		for mid in middleware.iter_mut() {
+			let derived = mid.process(event).await;
+			take_made(&mut **mid, derived, &mut pending, output);
+		}
+	}
+}
+
+/// Queue what a middleware published, then the event it returned, in the order the channel gets them
+fn take_made(
+	mid: &mut dyn Middleware,
+	derived: Option<EventEnvelope>,
+	pending: &mut VecDeque<EventEnvelope>,
+	output: &mut Vec<EventEnvelope>,
+) {
+	for event in core::iter::from_fn(|| mid.take_published()).chain(derived) {
+		pending.push_back(event);
+		output.push(event);
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn record(event: ReactorEvent) -> EventEnvelope {
+		EventEnvelope::at(event, SourceId(3), Instant::from_millis(1234))
+	}
+
+	fn encoded(event: ReactorEvent) -> Vec<u8> {
+		let mut buf = [0u8; RECORD_SIZE_MAX];
+		let len = encode(&record(event), &mut buf);
+		buf[..len].to_vec()
+	}
+
+	#[test]
+	fn records_round_trip() {
+		let events = [
+			ReactorEvent::Key(KeyEvent::Pressed(KeyCode::A)),
+			ReactorEvent::Key(KeyEvent::Held(KeyCode::ExSel)),
+			ReactorEvent::Key(KeyEvent::Released(KeyCode::MediaCalc)),
+			ReactorEvent::KeyboardReport {
+				modifier: KeyModifiers::from(0b1000_0001),
+				keycodes: [
+					KeyCode::LCtrl,
+					KeyCode::B,
+					KeyCode::None,
+					KeyCode::None,
+					KeyCode::None,
+					KeyCode::None,
+				],
+			},
+			ReactorEvent::Mouse { x: -3i32 as u32, y: 7 },
+			ReactorEvent::MouseButtons(0b101),
+			ReactorEvent::Analog6Axis(1, -2, 3, -4, 5, -6),
+			ReactorEvent::HardwareMappedBool(true, 4, 11),
+			ReactorEvent::Internal(InternalEvent::LayerChange(2)),
+			ReactorEvent::Passkey(Some(123456)),
+			ReactorEvent::Output { usb: true, ble: false },
+		];
+
+		let capture: Vec<u8> = events.iter().flat_map(|&event| encoded(event)).collect();
+		let expected: Vec<EventEnvelope> = events.iter().map(|&event| record(event)).collect();
+		assert_eq!(decode_all(&capture), expected);
+	}
+
+	#[test]
+	fn keycodes_outside_the_enum_are_malformed() {
+		for code in [0xA5, 0xC0, 0xDF, 0xFC, 0xFF] {
+			let mut key = encoded(ReactorEvent::Key(KeyEvent::Pressed(KeyCode::A)));
+			*key.last_mut().unwrap() = code;
+			assert_eq!(decode(&key), None, "key {:#x}", code);
+
+			let mut report = encoded(ReactorEvent::KeyboardReport {
+				modifier: KeyModifiers::default(),
+				keycodes: [KeyCode::None; 6],
+			});
+			*report.last_mut().unwrap() = code;
+			assert_eq!(decode(&report), None, "report {:#x}", code);
+		}
+	}
+
+	#[test]
+	fn decoding_stops_at_the_first_malformed_record() {
+		let mut capture = encoded(ReactorEvent::Battery(50));
+		let mut bad = encoded(ReactorEvent::Key(KeyEvent::Pressed(KeyCode::A)));
+		*bad.last_mut().unwrap() = 0xA5;
+		capture.extend(bad);
+		capture.extend(encoded(ReactorEvent::Battery(40)));
+
+		assert_eq!(decode_all(&capture), [record(ReactorEvent::Battery(50))]);
+	}
+}
@@ -6,6 +6,7 @@ use defmt::*;
 use embassy_nrf::saadc::{Gain, Reference, Resistor, Saadc, Time};
 use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
 use embassy_sync::pubsub::Publisher;
+use embassy_time::Duration;
 
 // TODO: Use a generics instead of nrf-specifics
 use embassy_nrf::peripherals::SAADC;
@@ -16,11 +17,15 @@ use crate::{Irqs, PUBSUB_CAPACITY, PUBSUB_PUBLISHERS, PUBSUB_SUBSCRIBERS};
 use reactor::reactor_event::*;
 use reactor::{Polled, RPublisher};
 
+pub const ANALOG_SOURCE: SourceId = SourceId(2);
+pub const ANALOG_PERIOD: u64 = 100;
+
 pub struct Analog<'a, const N: usize> {
 	input: Saadc<'a, N>,
 	last_state: [i16; N],
+	period: Duration,
 	channel:
-		Publisher<'a, CriticalSectionRawMutex, ReactorEvent, PUBSUB_CAPACITY, PUBSUB_SUBSCRIBERS, PUBSUB_PUBLISHERS>,
+		Publisher<'a, CriticalSectionRawMutex, EventEnvelope, PUBSUB_CAPACITY, PUBSUB_SUBSCRIBERS, PUBSUB_PUBLISHERS>,
 }
 
 impl<'a, const N: usize> Analog<'a, N> {
@@ -40,10 +45,16 @@ impl<'a, const N: usize> Analog<'a, N> {
 		Self {
 			input: saadc,
 			last_state: [0; N],
+			period: Duration::from_millis(ANALOG_PERIOD),
 			channel: crate::CHANNEL.publisher().unwrap(),
 		}
 	}
 
+	pub fn with_period(mut self, period: Duration) -> Self {
+		self.period = period;
+		self
+	}
+
 	async fn _poll_internal(&mut self) -> Option<[i16; N]> {
 		let mut buf = [0; N];
 
@@ -68,10 +79,16 @@ impl<'a> Polled for Analog<'a, 1> {
 	fn poll(&mut self) -> Pin<Box<dyn Future<Output = ()> + '_>> {
 		Box::pin(async {
 			if let Some(buf) = self._poll_internal().await {
-				self.channel.publish(ReactorEvent::Potentiometer { v: buf[0] }).await;
+				self.channel
+					.publish(EventEnvelope::new(ReactorEvent::Potentiometer { v: buf[0] }, ANALOG_SOURCE))
+					.await;
 			}
 		})
 	}
+
+	fn period(&self) -> Duration {
+		self.period
+	}
 }
 
 impl<'a> Polled for Analog<'a, 2> {
@@ -79,11 +96,15 @@ impl<'a> Polled for Analog<'a, 2> {
 		Box::pin(async {
 			if let Some(buf) = self._poll_internal().await {
 				self.channel
-					.publish(ReactorEvent::Joystick { x: buf[0], y: buf[1] })
+					.publish(EventEnvelope::new(ReactorEvent::Joystick { x: buf[0], y: buf[1] }, ANALOG_SOURCE))
 					.await;
 			}
 		})
 	}
+
+	fn period(&self) -> Duration {
+		self.period
+	}
 }
 
 impl<'a> Polled for Analog<'a, 3> {
@@ -91,15 +112,22 @@ impl<'a> Polled for Analog<'a, 3> {
 		Box::pin(async {
 			if let Some(buf) = self._poll_internal().await {
 				self.channel
-					.publish(ReactorEvent::FullJoystick {
-						x: buf[0],
-						y: buf[1],
-						z: buf[2],
-					})
+					.publish(EventEnvelope::new(
+						ReactorEvent::FullJoystick {
+							x: buf[0],
+							y: buf[1],
+							z: buf[2],
+						},
+						ANALOG_SOURCE,
+					))
 					.await;
 			}
 		})
 	}
+
+	fn period(&self) -> Duration {
+		self.period
+	}
 }
 
 impl<'a> Polled for Analog<'a, 6> {
@@ -107,16 +135,23 @@ impl<'a> Polled for Analog<'a, 6> {
 		Box::pin(async {
 			if let Some(buf) = self._poll_internal().await {
 				self.channel
-					.publish(ReactorEvent::Joystick6DoF {
-						x: buf[0],
-						y: buf[1],
-						z: buf[2],
-						rx: buf[3],
-						ry: buf[4],
-						rz: buf[5],
-					})
+					.publish(EventEnvelope::new(
+						ReactorEvent::Joystick6DoF {
+							x: buf[0],
+							y: buf[1],
+							z: buf[2],
+							rx: buf[3],
+							ry: buf[4],
+							rz: buf[5],
+						},
+						ANALOG_SOURCE,
+					))
 					.await;
 			}
 		})
 	}
+
+	fn period(&self) -> Duration {
+		self.period
+	}
 }
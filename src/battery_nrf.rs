@@ -0,0 +1,137 @@
+//! Battery charge, measured through the SAADC and looked up on the discharge curve of `[battery]`
+
+use alloc::boxed::Box;
+use core::future::Future;
+use core::pin::Pin;
+use defmt::*;
+
+use embassy_nrf::saadc::{ChannelConfig, Gain, Reference, Saadc, Time};
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+use embassy_sync::pubsub::Publisher;
+use embassy_time::Duration;
+
+// TODO: Use a generics instead of nrf-specifics
+use embassy_nrf::peripherals::SAADC;
+use embassy_nrf::saadc::Input;
+use embassy_nrf::Peripheral;
+
+use crate::config_types::BatteryConfig;
+use crate::{Irqs, PUBSUB_CAPACITY, PUBSUB_PUBLISHERS, PUBSUB_SUBSCRIBERS};
+use reactor::reactor_event::*;
+use reactor::{Polled, RPublisher};
+
+pub const BATTERY_SOURCE: SourceId = SourceId(6);
+/// Full scale of the SAADC in mV, the 0.6V internal reference with a gain of 1/6
+const FULL_SCALE_MV: u32 = 3600;
+/// Samples are 12 bits by default
+const RESOLUTION: u32 = 1 << 12;
+
+pub struct Battery<'a> {
+	input: Saadc<'a, 1>,
+	config: &'static BatteryConfig,
+	/// Battery voltage over the sampled one, including the /5 of VDDH
+	scale: f32,
+	last_level: Option<u8>,
+	/// Called once each time the charge drops under `config.low`
+	low_battery: Option<fn(u8)>,
+	channel:
+		Publisher<'a, CriticalSectionRawMutex, EventEnvelope, PUBSUB_CAPACITY, PUBSUB_SUBSCRIBERS, PUBSUB_PUBLISHERS>,
+}
+
+impl<'a> Battery<'a> {
+	pub fn new(p_saadc: SAADC, input: impl Peripheral<P = impl Input> + 'a, config: &'static BatteryConfig) -> Self {
+		let saadc_config = embassy_nrf::saadc::Config::default();
+		let mut cc = ChannelConfig::single_ended(input);
+		cc.gain = Gain::GAIN1_6;
+		cc.reference = Reference::INTERNAL;
+		// Battery dividers are high impedance to not drain the battery
+		cc.time = Time::_40US;
+		let saadc: Saadc<'a, 1> = Saadc::new(p_saadc, Irqs, saadc_config, [cc]);
+
+		let scale = match config.input {
+			"vddh" => config.divider * 5.0,
+			_ => config.divider,
+		};
+
+		Self {
+			input: saadc,
+			config,
+			scale,
+			last_level: None,
+			low_battery: None,
+			channel: crate::CHANNEL.publisher().unwrap(),
+		}
+	}
+
+	/// Run `hook` with the charge when it drops under `battery.low`
+	pub fn with_low_battery(mut self, hook: fn(u8)) -> Self {
+		self.low_battery = Some(hook);
+		self
+	}
+
+	async fn millivolts(&mut self) -> u32 {
+		let mut buf = [0; 1];
+		self.input.sample(&mut buf).await;
+
+		// Noise can make a grounded input slightly negative
+		let sampled = buf[0].max(0) as u32 * FULL_SCALE_MV / RESOLUTION;
+		(sampled as f32 * self.scale) as u32
+	}
+
+	/// Charge in % of `mv`, linearly interpolated between the points of the curve
+	fn level(&self, mv: u32) -> u8 {
+		let curve = &self.config.curve;
+		let (Some(full), Some(empty)) = (curve.first(), curve.last()) else {
+			return 0;
+		};
+
+		if mv >= full.mv {
+			return full.percent;
+		}
+		if mv <= empty.mv {
+			return empty.percent;
+		}
+
+		curve
+			.windows(2)
+			.find(|points| mv > points[1].mv)
+			.map_or(empty.percent, |points| {
+				let (high, low) = (points[0], points[1]);
+				let percent = (high.percent - low.percent) as u32 * (mv - low.mv) / (high.mv - low.mv);
+				low.percent + percent as u8
+			})
+	}
+}
+
+impl<'a> RPublisher for Battery<'a> {}
+
+impl<'a> Polled for Battery<'a> {
+	fn poll(&mut self) -> Pin<Box<dyn Future<Output = ()> + '_>> {
+		Box::pin(async {
+			let mv = self.millivolts().await;
+			let level = self.level(mv);
+			debug!("Battery: {}mV, {}%", mv, level);
+
+			if self.last_level == Some(level) {
+				return;
+			}
+
+			let low = self.config.low;
+			if level < low && self.last_level.map_or(true, |last| last >= low) {
+				warn!("Battery low: {}%", level);
+				if let Some(hook) = self.low_battery {
+					hook(level);
+				}
+			}
+			self.last_level = Some(level);
+
+			self.channel
+				.publish(EventEnvelope::new(ReactorEvent::Battery(level), BATTERY_SOURCE))
+				.await;
+		})
+	}
+
+	fn period(&self) -> Duration {
+		Duration::from_millis(self.config.period)
+	}
+}
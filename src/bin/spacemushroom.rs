@@ -20,7 +20,8 @@ async fn main(spawner: Spawner) {
 		Into::<saadc::AnyInput>::into(p.P0_30),
 		Into::<saadc::AnyInput>::into(p.P0_31),
 	]));
-	spawner.spawn(poller_task(analog)).unwrap();
+	let pubs_task = publishers_task!([analog]);
+	spawner.spawn(pubs_task).unwrap();
 
 	// --- Setup USB HID consumer ---
 	let mut usb_builder = usb_init(p.USBD);
@@ -0,0 +1,488 @@
+//! HID over GATT client - connects to a BLE keyboard or mouse and publishes what's typed and moved on
+//! it as events, so the board can relay it (over USB, through its own middleware) like a dongle
+
+use core::cell::Cell;
+use core::pin::Pin;
+
+use alloc::boxed::Box;
+use defmt::*;
+use ekv::Database;
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+use embassy_sync::signal::Signal;
+use embassy_time::{with_timeout, Duration};
+use futures::Future;
+use nrf_softdevice::ble::gatt_client::{self, Characteristic, Descriptor, DiscoverError, HvxType};
+use nrf_softdevice::ble::security::{IoCapabilities, SecurityHandler};
+use nrf_softdevice::ble::{central, Address, Connection, EncryptionInfo, IdentityKey, MasterId, SecurityMode, Uuid};
+use nrf_softdevice::Softdevice;
+use static_cell::make_static;
+
+use crate::ble_hid::{conn_params, tx_power, Peer, PEER_SIZE};
+use crate::{config, CHANNEL};
+use reactor::reactor_event::*;
+use reactor::{Polled, RPublisher};
+
+pub const BLE_CENTRAL_SOURCE: SourceId = SourceId(8);
+
+/// Key of the bond with the device in the database
+const BOND_KEY: &[u8] = b"ble/central/peer";
+/// How long the device gets to encrypt the link, passkey typing included
+const SECURITY_TIMEOUT: Duration = Duration::from_secs(60);
+
+const HID_SERVICE: u16 = 0x1812;
+const PROTOCOL_MODE: u16 = 0x2A4E;
+const BOOT_KEYBOARD_INPUT: u16 = 0x2A22;
+const BOOT_MOUSE_INPUT: u16 = 0x2A33;
+const CCCD: u16 = 0x2902;
+/// Modifiers, reserved, then the usages of up to 6 keys
+const BOOT_KEYBOARD_SIZE: usize = 8;
+/// Usage every key is reported as while too many are held
+const ERROR_ROLL_OVER: u8 = 0x01;
+/// Usage of the first modifier bit, Left Control
+const MODIFIERS_USAGE: u8 = 0xE0;
+
+/// The boot protocol side of the HID service of the device. Every HID keyboard and mouse has it, and
+/// its reports have a fixed layout, so there's no report map to parse.
+pub struct HidClient {
+	protocol_mode: Option<u16>,
+	/// Value and CCCD handles of the boot keyboard input report
+	keyboard: Option<(u16, u16)>,
+	/// Value and CCCD handles of the boot mouse input report
+	mouse: Option<(u16, u16)>,
+}
+
+pub enum HidClientEvent {
+	Keyboard([u8; BOOT_KEYBOARD_SIZE]),
+	/// Buttons, then the movement along x and y
+	Mouse(u8, i8, i8),
+}
+
+impl gatt_client::Client for HidClient {
+	type Event = HidClientEvent;
+
+	fn uuid() -> Uuid {
+		Uuid::new_16(HID_SERVICE)
+	}
+
+	fn new_undiscovered(_conn: Connection) -> Self {
+		Self {
+			protocol_mode: None,
+			keyboard: None,
+			mouse: None,
+		}
+	}
+
+	fn discovered_characteristic(&mut self, characteristic: &Characteristic, descriptors: &[Descriptor]) {
+		let Some(uuid) = characteristic.uuid else {
+			return;
+		};
+		let value = characteristic.handle_value;
+		let cccd = descriptors
+			.iter()
+			.find(|descriptor| descriptor.uuid == Some(Uuid::new_16(CCCD)))
+			.map(|descriptor| (value, descriptor.handle));
+
+		if uuid == Uuid::new_16(PROTOCOL_MODE) {
+			self.protocol_mode = Some(value);
+		} else if uuid == Uuid::new_16(BOOT_KEYBOARD_INPUT) {
+			self.keyboard = cccd;
+		} else if uuid == Uuid::new_16(BOOT_MOUSE_INPUT) {
+			self.mouse = cccd;
+		}
+	}
+
+	fn discovery_complete(&mut self) -> Result<(), DiscoverError> {
+		// The boot reports only flow once the device is switched to the boot protocol
+		if self.protocol_mode.is_none() || (self.keyboard.is_none() && self.mouse.is_none()) {
+			return Err(DiscoverError::ServiceIncomplete);
+		}
+		Ok(())
+	}
+
+	fn on_hvx(&self, _conn: &Connection, type_: HvxType, handle: u16, data: &[u8]) -> Option<Self::Event> {
+		if !matches!(type_, HvxType::Notification) {
+			return None;
+		}
+
+		if self.keyboard.is_some_and(|(value, _)| value == handle) {
+			// Short reports leave the last keys up
+			let mut report = [0u8; BOOT_KEYBOARD_SIZE];
+			let len = data.len().min(BOOT_KEYBOARD_SIZE);
+			report[..len].copy_from_slice(&data[..len]);
+			Some(HidClientEvent::Keyboard(report))
+		} else if self.mouse.is_some_and(|(value, _)| value == handle) {
+			// Buttons, then the movement - anything after that is the device's own
+			match data {
+				[buttons, x, y, ..] => Some(HidClientEvent::Mouse(*buttons, *x as i8, *y as i8)),
+				_ => None,
+			}
+		} else {
+			None
+		}
+	}
+}
+
+fn publish(event: ReactorEvent) {
+	// From the notification callback, which can't wait
+	CHANNEL
+		.immediate_publisher()
+		.publish_immediate(EventEnvelope::new(event, BLE_CENTRAL_SOURCE));
+}
+
+/// A key of the device went down or up - as its keycode, or by usage for the keymap to map it
+fn publish_key(usage: u8, pressed: bool) {
+	let event = if config::BLE_CENTRAL.keymap {
+		ReactorEvent::HardwareMappedBool(pressed, (usage >> 4) as usize, (usage & 0x0F) as usize)
+	} else {
+		// Whatever the device sends, only known keycodes get through
+		let Some(code) = KeyCode::from_u8(usage) else {
+			debug!("Dropping the unknown usage {=u8:#x} of the device", usage);
+			return;
+		};
+
+		if pressed {
+			ReactorEvent::Key(KeyEvent::Pressed(code))
+		} else {
+			ReactorEvent::Key(KeyEvent::Released(code))
+		}
+	};
+
+	publish(event);
+}
+
+/// Pairs and bonds with the device, keeping the bond in RAM for the SoftDevice callbacks
+#[derive(Default)]
+struct CentralBond {
+	peer: Cell<Option<Peer>>,
+	/// Raised once the link is encrypted
+	encrypted: Signal<CriticalSectionRawMutex, ()>,
+	/// Raised when the device bonded, for the bond to be stored
+	bonded: Signal<CriticalSectionRawMutex, ()>,
+	/// A passkey was published for the board to show
+	showing_passkey: Cell<bool>,
+}
+
+impl CentralBond {
+	/// Take the passkey shown down, if there's one
+	fn end_pairing(&self) {
+		if self.showing_passkey.replace(false) {
+			publish(ReactorEvent::Passkey(None));
+		}
+	}
+}
+
+impl SecurityHandler for CentralBond {
+	fn io_capabilities(&self) -> IoCapabilities {
+		match config::BLE_CENTRAL.io_capabilities {
+			"DisplayOnly" => IoCapabilities::DisplayOnly,
+			_ => IoCapabilities::None,
+		}
+	}
+
+	fn can_bond(&self, _conn: &Connection) -> bool {
+		true
+	}
+
+	fn display_passkey(&self, passkey: &[u8; 6]) {
+		// ASCII digits
+		let passkey = passkey
+			.iter()
+			.fold(0, |passkey, digit| passkey * 10 + (digit - b'0') as u32);
+		info!("Passkey to type on the device: {}", passkey);
+
+		self.showing_passkey.set(true);
+		publish(ReactorEvent::Passkey(Some(passkey)));
+	}
+
+	fn on_security_update(&self, _conn: &Connection, security_mode: SecurityMode) {
+		info!("Device link security: {:?}", security_mode);
+
+		if !matches!(security_mode, SecurityMode::NoAccess | SecurityMode::Open) {
+			self.encrypted.signal(());
+		}
+		self.end_pairing();
+	}
+
+	fn on_bonded(&self, _conn: &Connection, master_id: MasterId, key: EncryptionInfo, peer_id: IdentityKey) {
+		info!("Bonded with {}", peer_id.addr);
+
+		self.peer.set(Some(Peer {
+			master_id,
+			key,
+			peer_id,
+		}));
+		self.bonded.signal(());
+	}
+}
+
+/// Relays a BLE keyboard or mouse: its keys are published as `Key` events (or `HardwareMappedBool`
+/// ones laid out by usage when the board has a keymap), its buttons as `MouseButtons` events and its
+/// movement as `Mouse` events
+pub struct BleCentral {
+	softdevice: &'static Softdevice,
+	db: &'static Database<&'static mut crate::Flash, CriticalSectionRawMutex>,
+	bond: &'static CentralBond,
+	/// Whether the bond was read from `db` yet
+	restored: bool,
+	/// Last boot keyboard report, to tell what was pressed and released
+	keys: [u8; BOOT_KEYBOARD_SIZE],
+	/// Last mouse buttons, only published when they change
+	buttons: u8,
+}
+
+impl BleCentral {
+	pub fn new(
+		softdevice: &'static Softdevice,
+		db: &'static Database<&'static mut crate::Flash, CriticalSectionRawMutex>,
+	) -> Self {
+		Self {
+			softdevice,
+			db,
+			bond: make_static!(CentralBond::default()),
+			restored: false,
+			keys: [0; BOOT_KEYBOARD_SIZE],
+			buttons: 0,
+		}
+	}
+
+	async fn restore(&mut self) {
+		let mut buf = [0u8; PEER_SIZE];
+		let mut rtx = self.db.read_transaction().await;
+		if let Ok(len) = rtx.read(BOND_KEY, &mut buf).await {
+			self.bond.peer.set(Peer::from_bytes(&buf[..len]));
+		}
+		if let Some(peer) = self.bond.peer.get() {
+			info!("Restored the bond with {}", peer.peer_id.addr);
+		}
+		self.restored = true;
+	}
+
+	/// Write the bond to the database, or drop it from there when there's none
+	async fn store(&self) {
+		let peer = self.bond.peer.get();
+		let mut wtx = self.db.write_transaction().await;
+		let written = match &peer {
+			Some(peer) => wtx.write(BOND_KEY, &peer.to_bytes()).await,
+			None => wtx.delete(BOND_KEY).await,
+		};
+
+		match written {
+			Ok(()) => match wtx.commit().await {
+				Ok(()) => debug!("Stored the bond with the device"),
+				Err(e) => error!("Could not store the bond with the device: {:?}", e),
+			},
+			Err(e) => error!("Could not store the bond with the device: {:?}", e),
+		}
+	}
+
+	/// The device of `ble_central.address`, by its own address or a private one of the bond
+	async fn connect(&self) -> Option<Connection> {
+		// Written most significant byte first, the SoftDevice has them the other way around
+		let mut wanted = [0u8; 6];
+		for (byte, part) in wanted.iter_mut().rev().zip(config::BLE_CENTRAL.address.split(':')) {
+			// Validated by the build script
+			*byte = u8::from_str_radix(part, 16).unwrap();
+		}
+		let bonded = self.bond.peer.get().filter(|peer| peer.peer_id.addr.bytes() == wanted);
+
+		let scan_config = central::ScanConfig {
+			// Devices often only list their services in the scan response
+			active: true,
+			tx_power: tx_power(config::BLE.tx_power),
+			..Default::default()
+		};
+
+		info!("Scanning for the HID device...");
+		let found = central::scan(self.softdevice, &scan_config, |report| {
+			if report.type_.connectable() == 0 {
+				return None;
+			}
+
+			// Never anyone else, whatever they advertise - they'd get to type on the host
+			let address = Address::from_raw(report.peer_addr);
+			let matches = match bonded {
+				// Bonded devices come back with their own address, or a private one we can resolve
+				Some(peer) => peer.peer_id.is_match(address),
+				None => address.bytes() == wanted,
+			};
+
+			matches.then_some(address)
+		})
+		.await;
+
+		let address = match found {
+			Ok(address) => address,
+			Err(e) => {
+				warn!("Scanning failed: {:?}", e);
+				return None;
+			},
+		};
+
+		info!("Connecting to {}...", address);
+		let whitelist = [&address];
+		let connect_config = central::ConnectConfig {
+			scan_config: central::ScanConfig {
+				whitelist: Some(&whitelist),
+				tx_power: tx_power(config::BLE.tx_power),
+				..Default::default()
+			},
+			conn_params: conn_params(config::BLE.latency),
+			..Default::default()
+		};
+
+		match central::connect_with_security(self.softdevice, &connect_config, self.bond).await {
+			Ok(conn) => Some(conn),
+			Err(e) => {
+				warn!("Could not connect to {}: {:?}", address, e);
+				None
+			},
+		}
+	}
+
+	/// Encrypt the link with the bond, or pair anew - HID devices keep their reports to encrypted links
+	async fn secure(&self, conn: &Connection) -> bool {
+		self.bond.encrypted.reset();
+		self.bond.bonded.reset();
+
+		let bonded = self
+			.bond
+			.peer
+			.get()
+			.filter(|peer| peer.peer_id.is_match(conn.peer_address()));
+		let asked = match bonded {
+			Some(peer) => conn
+				.encrypt(&peer.master_id, &peer.key)
+				.map_err(|e| warn!("Could not encrypt the link: {:?}", e)),
+			None => conn
+				.request_security()
+				.map_err(|e| warn!("Could not pair with the device: {:?}", e)),
+		};
+		if asked.is_err() {
+			return false;
+		}
+
+		if with_timeout(SECURITY_TIMEOUT, self.bond.encrypted.wait())
+			.await
+			.is_err()
+		{
+			warn!("The device didn't encrypt the link");
+			// It most likely forgot us, so pair next time
+			if bonded.is_some() {
+				self.bond.peer.set(None);
+				self.store().await;
+			}
+			return false;
+		}
+
+		if self.bond.bonded.try_take().is_some() {
+			self.store().await;
+		}
+		true
+	}
+
+	/// Switch the device to the boot protocol and publish its reports until it goes away
+	async fn relay(&mut self, conn: &Connection) {
+		let client: HidClient = match gatt_client::discover(conn).await {
+			Ok(client) => client,
+			Err(e) => {
+				warn!("The device has no usable HID service: {:?}", e);
+				return;
+			},
+		};
+
+		// Checked by `discovery_complete`
+		let protocol_mode = client.protocol_mode.unwrap();
+		if let Err(e) = gatt_client::write_without_response(conn, protocol_mode, &[0]).await {
+			warn!("Could not switch the device to the boot protocol: {:?}", e);
+			return;
+		}
+		for (_, cccd) in [client.keyboard, client.mouse].into_iter().flatten() {
+			if let Err(e) = gatt_client::write(conn, cccd, &[1, 0]).await {
+				warn!("Could not subscribe to the reports of the device: {:?}", e);
+				return;
+			}
+		}
+
+		info!("Relaying the device");
+		gatt_client::run(conn, &client, |event| match event {
+			HidClientEvent::Keyboard(report) => self.keyboard(report),
+			HidClientEvent::Mouse(buttons, x, y) => self.mouse(buttons, x, y),
+		})
+		.await;
+	}
+
+	/// Publish the buttons of a boot mouse report if they changed, then its movement if it has any
+	fn mouse(&mut self, buttons: u8, x: i8, y: i8) {
+		if core::mem::replace(&mut self.buttons, buttons) != buttons {
+			publish(ReactorEvent::MouseButtons(buttons));
+		}
+		if x != 0 || y != 0 {
+			// Signed, like `ble_hid` reads them
+			publish(ReactorEvent::Mouse {
+				x: x as i32 as u32,
+				y: y as i32 as u32,
+			});
+		}
+	}
+
+	/// Publish what changed between the last boot keyboard report and `report`
+	fn keyboard(&mut self, report: [u8; BOOT_KEYBOARD_SIZE]) {
+		// The device can't tell which keys are held, so they stay as they were
+		if report[2..].contains(&ERROR_ROLL_OVER) {
+			return;
+		}
+		let last = core::mem::replace(&mut self.keys, report);
+
+		for bit in 0..8 {
+			let (was, is) = (last[0] & 1 << bit != 0, report[0] & 1 << bit != 0);
+			if was != is {
+				publish_key(MODIFIERS_USAGE + bit, is);
+			}
+		}
+		for &usage in last[2..]
+			.iter()
+			.filter(|&&usage| usage != 0 && !report[2..].contains(&usage))
+		{
+			publish_key(usage, false);
+		}
+		for &usage in report[2..]
+			.iter()
+			.filter(|&&usage| usage != 0 && !last[2..].contains(&usage))
+		{
+			publish_key(usage, true);
+		}
+	}
+}
+
+impl RPublisher for BleCentral {}
+
+impl Polled for BleCentral {
+	/// One connection to the device, from scanning for it to losing it
+	fn poll(&mut self) -> Pin<Box<dyn Future<Output = ()> + '_>> {
+		Box::pin(async move {
+			if !self.restored {
+				self.restore().await;
+			}
+
+			let Some(conn) = self.connect().await else {
+				return;
+			};
+			info!("Connected to {}", conn.peer_address());
+
+			if self.secure(&conn).await {
+				self.relay(&conn).await;
+			}
+			conn.disconnect().ok();
+			info!("Lost the device");
+
+			// Keys and buttons held when it went away would stay down otherwise
+			self.keyboard([0; BOOT_KEYBOARD_SIZE]);
+			self.mouse(0, 0, 0);
+			self.bond.end_pairing();
+		})
+	}
+
+	fn period(&self) -> Duration {
+		Duration::from_millis(config::BLE_CENTRAL.period)
+	}
+}
@@ -1,14 +1,23 @@
 use core::cell::{Cell, RefCell};
+use core::fmt::Write;
+use core::future::pending;
 use core::pin::Pin;
+use core::str::FromStr;
+use core::sync::atomic::{AtomicBool, Ordering};
 
 use alloc::boxed::Box;
 use alloc::sync::Arc;
-use alloc::vec::Vec;
 use ekv::Database;
 use embassy_executor::task;
+use embassy_futures::join::join;
+use embassy_futures::select::{select, select3, Either, Either3};
 use embassy_sync::blocking_mutex::raw::{CriticalSectionRawMutex, ThreadModeRawMutex};
+use embassy_sync::blocking_mutex::Mutex as BlockingMutex;
+use embassy_sync::channel::Channel;
 use embassy_sync::mutex::Mutex;
 use embassy_sync::pubsub::Subscriber;
+use embassy_sync::signal::Signal;
+use embassy_time::{with_timeout, Duration};
 use futures::Future;
 use heapless::String;
 use nrf_softdevice::ble::advertisement_builder::{
@@ -17,40 +26,98 @@ use nrf_softdevice::ble::advertisement_builder::{
 use nrf_softdevice::ble::gatt_server::builder::ServiceBuilder;
 use nrf_softdevice::ble::gatt_server::characteristic::{Attribute, Metadata, Properties};
 use nrf_softdevice::ble::gatt_server::{RegisterError, Service};
-use nrf_softdevice::ble::security::SecurityHandler;
+use nrf_softdevice::ble::security::{IoCapabilities, SecurityHandler};
 use nrf_softdevice::ble::{
-	gatt_server, peripheral, Connection, EncryptionInfo, GattValue, IdentityKey, MasterId, Uuid,
+	gatt_server, peripheral, Address, AddressType, Connection, EncryptionInfo, GattValue, IdentityKey,
+	IdentityResolutionKey, MasterId, PasskeyReply, SecurityMode, TxPower, Uuid,
 };
-use nrf_softdevice::Softdevice;
-use static_cell::make_static;
+use nrf_softdevice::{raw, Softdevice};
+use serde::Serialize;
 use ssmarshal::serialize;
-use usbd_hid::descriptor::{KeyboardReport, SerializedDescriptor};
+use static_cell::make_static;
+use strum::EnumString;
+use usbd_hid::descriptor::{KeyboardReport, MediaKeyboardReport, MouseReport};
 
 use defmt::*;
 
-use crate::{PUBSUB_CAPACITY, PUBSUB_PUBLISHERS, PUBSUB_SUBSCRIBERS};
+use crate::output::{Transport, OUTPUT};
+use crate::report_maps::{consumer_usage, Report, SpaceMouseReport, REPORT_SIZE_MAX};
+use crate::{config, CHANNEL, PUBSUB_CAPACITY, PUBSUB_PUBLISHERS, PUBSUB_SUBSCRIBERS};
+use reactor::queue::OverflowPolicy;
 use reactor::reactor_event::*;
 use reactor::RSubscriber;
 
+pub const BLE_HID_SOURCE: SourceId = SourceId(5);
+
 #[task]
-pub async fn ble_hid_task(sd: &'static Softdevice, server: &'static Server, db: &'static mut Database<&mut crate::Flash, CriticalSectionRawMutex>) {
+pub async fn ble_hid_task(sd: &'static Softdevice, server: &'static Server, db: &'static Database<&'static mut crate::Flash, CriticalSectionRawMutex>) {
 	info!("BLE HID task started");
-	let security_handler = make_static!(Bonder::new(db));
+	let bonder: &'static Bonder = make_static!(Bonder::new(db).await);
 
-	loop {
-		info!("Waiting for connection");
-		let conn = BleHid::connect(sd, security_handler).await;
+	// Hosts that read the preferred connection parameters get ours before they pick any
+	let ret = unsafe { raw::sd_ble_gap_ppcp_set(&conn_params(config::BLE.latency)) };
+	if ret != raw::NRF_SUCCESS {
+		warn!("Could not set the preferred connection parameters: {}", ret);
+	}
+
+	let connections = async {
+		loop {
+			info!("Waiting for connection on slot {}", bonder.selected.get());
+			let conn = match select(BleHid::connect(sd, bonder), bonder.switched()).await {
+				Either::First(Some(conn)) => conn,
+				Either::First(None) => {
+					info!("Stopped advertising until a key is pressed");
+					ADVERTISE.reset();
+					select(ADVERTISE.wait(), bonder.switched()).await;
+					continue;
+				},
+				Either::Second(()) => continue,
+			};
+
+			info!("Got connection: {:?}", conn.peer_address());
+			let mut active_conn = server.hid.active_conn_handle.lock().await;
+			*active_conn = conn.handle();
+			drop(active_conn);
+			server.hid.reset(sd);
+			info!("Updated active connection handle");
+			OUTPUT.set_ble_connected(true);
+
+			// Whatever the host picked, ask for ours
+			if let Err(e) = conn.set_conn_params(conn_params(config::BLE.latency)) {
+				warn!("Could not update the connection parameters: {:?}", e);
+			}
 
-		info!("Got connection: {:?}", conn.peer_address());
-		let mut active_conn = server.hid.active_conn_handle.lock().await;
-		*active_conn = conn.handle();
-		drop(active_conn);
-		info!("Updated active connection handle");
+			// Bonded hosts encrypt the link on their own, the others are asked to pair
+			if config::BLE_HID.require_encryption {
+				if let Err(e) = conn.request_security() {
+					warn!("Could not ask the host to pair: {:?}", e);
+				}
+			}
 
-		gatt_server::run(&conn, server, |_| {}).await;
+			let events = gatt_server::run(&conn, server, |event| {
+				if let ServerEvent::Hid(HIDServiceEvent::Leds(leds)) = event {
+					CHANNEL
+						.immediate_publisher()
+						.publish_immediate(EventEnvelope::new(ReactorEvent::from_leds(leds), BLE_HID_SOURCE));
+				}
+			});
 
-		info!("Connection lost");
-	}
+			match select3(events, bonder.switched(), server.hid.pace(&conn)).await {
+				Either3::First(_) => info!("Connection lost"),
+				Either3::Second(()) => {
+					info!("Dropping the connection for slot {}", bonder.selected.get());
+					conn.disconnect().ok();
+				},
+				Either3::Third(never) => never,
+			}
+			*server.hid.active_conn_handle.lock().await = None;
+			OUTPUT.set_ble_connected(false);
+			ENCRYPTED.store(false, Ordering::Relaxed);
+			bonder.end_pairing();
+		}
+	};
+
+	join(connections, bonder.persist()).await;
 }
 
 #[nrf_softdevice::gatt_service(uuid = "180f")]
@@ -67,7 +134,7 @@ pub struct BatteryService {
 // }
 
 #[repr(u8)]
-#[derive(Clone, Copy)]
+#[derive(Clone, Copy, EnumString)]
 pub enum VidSource {
 	BluetoothSIG = 1,
 	UsbIF = 2,
@@ -135,19 +202,41 @@ pub struct DeviceInformationService {
 	pnp_id: PnPID,
 }
 
+/// Input reports a HID service can have, one per `Report`
+const REPORTS_MAX: usize = 4;
+/// Most a characteristic can hold
+const REPORT_MAP_MAX: usize = 512;
+
 pub struct HIDService {
 	pub hid_info: u16,
 	pub report_map: u16,
 	pub hid_control: u16,
 	pub protocol_mode: u16,
-	pub input_keyboard: u16,
-	// pub output_keyboard: u16,
-	// pub input_media_keys: u16,
+	/// Input characteristic of each report of `config::BLE_HID`, whose report ID is its position + 1
+	pub inputs: heapless::Vec<(Report, u16), REPORTS_MAX>,
+	/// Output characteristic of the keyboard LEDs, when there's a keyboard
+	pub output_keyboard: Option<u16>,
+	/// Boot protocol keyboard input and LED output, when there's a keyboard
+	pub boot_keyboard_input: Option<u16>,
+	pub boot_keyboard_output: Option<u16>,
+	/// Whether the host switched to the boot protocol with the Protocol Mode
+	boot_protocol: AtomicBool,
+	/// Whether the host suspended itself with the HID Control Point
+	suspended: AtomicBool,
+	/// Raised by every report, to keep the connection snappy
+	activity: Signal<CriticalSectionRawMutex, ()>,
 	pub active_conn_handle: Arc<Mutex<ThreadModeRawMutex, Option<u16>>>,
 }
 
 impl HIDService {
 	pub fn new(sd: &mut Softdevice) -> Result<Self, RegisterError> {
+		// Validated by the build script
+		let reports = config::BLE_HID
+			.reports
+			.iter()
+			.map(|report| Report::from_str(report).unwrap())
+			.collect::<heapless::Vec<Report, REPORTS_MAX>>();
+
 		let mut service_builder = ServiceBuilder::new(sd, Uuid::new_16(0x1812))?;
 
 		let hid_info = service_builder.add_characteristic(
@@ -157,9 +246,16 @@ impl HIDService {
 		)?;
 		let hid_info_handle = hid_info.build();
 
+		// Each descriptor goes under its report ID, which the hosts strip from the input reports
+		let mut report_map = heapless::Vec::<u8, REPORT_MAP_MAX>::new();
+		for (index, report) in reports.iter().enumerate() {
+			report_map.extend_from_slice(&[0x85, index as u8 + 1]).unwrap();
+			report_map.extend_from_slice(report.desc()).unwrap();
+		}
+
 		let report_map = service_builder.add_characteristic(
 			Uuid::new_16(0x2A4B),
-			Attribute::new(KeyboardReport::desc()),
+			Attribute::new(report_map.as_slice()),
 			Metadata::new(Properties::new().read()),
 		)?;
 		let report_map_handle = report_map.build();
@@ -171,17 +267,53 @@ impl HIDService {
 		)?;
 		let hid_control_handle = hid_control.build();
 
-		let mut input_keyboard = service_builder.add_characteristic(
-			Uuid::new_16(0x2A4D),
-			Attribute::new([0u8; 8]),
-			Metadata::new(Properties::new().read().notify()),
-		)?;
-		let _input_keyboard_desc =
-			// TODO: This is the ID of descriptor. Wtf to do with it? let it hardcoded?
-			input_keyboard.add_descriptor(Uuid::new_16(0x2908), Attribute::new([1, 1u8]))?; // First is ID (e.g. 1 for keyboard 2 for media keys), second is in/out
-		let input_keyboard_handle = input_keyboard.build();
+		let mut inputs = heapless::Vec::new();
+		for (index, &report) in reports.iter().enumerate() {
+			let mut input = service_builder.add_characteristic(
+				Uuid::new_16(0x2A4D),
+				Attribute::new(&[0u8; REPORT_SIZE_MAX][..report.size()]),
+				Metadata::new(Properties::new().read().notify()),
+			)?;
+			// Report Reference - the report ID and 1 for an input report
+			input.add_descriptor(Uuid::new_16(0x2908), Attribute::new([index as u8 + 1, 1u8]))?;
+			inputs.push((report, input.build().value_handle)).unwrap();
+		}
 
-		// TODO: Handle outputs
+		let keyboard = reports.iter().position(|&report| report == Report::KeyboardReport);
+		let (output_keyboard, boot_keyboard_input, boot_keyboard_output) = match keyboard {
+			Some(index) => {
+				let mut output = service_builder.add_characteristic(
+					Uuid::new_16(0x2A4D),
+					Attribute::new([0u8]),
+					Metadata::new(Properties::new().read().write().write_without_response()),
+				)?;
+				// Report Reference - the report ID and 2 for an output report
+				output.add_descriptor(Uuid::new_16(0x2908), Attribute::new([index as u8 + 1, 2u8]))?;
+				let output_handle = output.build();
+
+				// The boot protocol reports have the same layout, without a report ID
+				let boot_input = service_builder.add_characteristic(
+					Uuid::new_16(0x2A22),
+					Attribute::new([0u8; 8]),
+					Metadata::new(Properties::new().read().notify()),
+				)?;
+				let boot_input_handle = boot_input.build();
+
+				let boot_output = service_builder.add_characteristic(
+					Uuid::new_16(0x2A32),
+					Attribute::new([0u8]),
+					Metadata::new(Properties::new().read().write().write_without_response()),
+				)?;
+				let boot_output_handle = boot_output.build();
+
+				(
+					Some(output_handle.value_handle),
+					Some(boot_input_handle.value_handle),
+					Some(boot_output_handle.value_handle),
+				)
+			},
+			None => (None, None, None),
+		};
 
 		let protocol_mode = service_builder.add_characteristic(
 			Uuid::new_16(0x2A4E),
@@ -197,38 +329,158 @@ impl HIDService {
 			report_map: report_map_handle.value_handle,
 			hid_control: hid_control_handle.value_handle,
 			protocol_mode: protocol_mode_handle.value_handle,
-			input_keyboard: input_keyboard_handle.value_handle,
+			inputs,
+			output_keyboard,
+			boot_keyboard_input,
+			boot_keyboard_output,
+			boot_protocol: AtomicBool::new(false),
+			suspended: AtomicBool::new(false),
+			activity: Signal::new(),
 			active_conn_handle: Arc::new(Mutex::new(None)),
 		})
 	}
 
-	pub async fn send_report(&self, report: &KeyboardReport) {
-		let active_conn = self.active_conn_handle.lock().await;
-		if active_conn.is_none() {
-			info!("No active connection");
+	/// Back to the report protocol and awake, as each new connection starts
+	pub fn reset(&self, sd: &Softdevice) {
+		self.boot_protocol.store(false, Ordering::Relaxed);
+		self.suspended.store(false, Ordering::Relaxed);
+		self.activity.reset();
+		if let Err(e) = gatt_server::set_value(sd, self.protocol_mode, &[1]) {
+			warn!("Could not reset the protocol mode: {:?}", e);
+		}
+	}
+
+	/// Notify `report` on the input characteristic of `kind`, if the reports go out on BLE
+	pub async fn send_report(&self, kind: Report, report: &impl Serialize) {
+		// Keys typed for the passkey are for the board alone
+		if !OUTPUT.to_ble() || OUTPUT.held() {
 			return;
 		}
 
-		let conn = Connection::from_handle(active_conn.unwrap()).unwrap();
-		drop(active_conn);
+		self.notify_report(kind, report).await;
+	}
+
+	/// Release everything the host might still see held, once the reports stopped going out on BLE
+	pub async fn release_all(&self) {
+		let keyboard = KeyboardReport {
+			modifier: 0,
+			reserved: 0,
+			leds: 0,
+			keycodes: [0; 6],
+		};
+		self.notify_report(Report::KeyboardReport, &keyboard).await;
+		self.notify_report(Report::MediaKeyboardReport, &MediaKeyboardReport { usage_id: 0 })
+			.await;
+		let mouse = MouseReport {
+			buttons: 0,
+			x: 0,
+			y: 0,
+			wheel: 0,
+			pan: 0,
+		};
+		self.notify_report(Report::MouseReport, &mouse).await;
+	}
 
-		// TODO: The size needs to be a generic or const
-		let mut report_bytes = [0u8; 9];
-		serialize(&mut report_bytes, report).expect("Failed to serialize report");
+	/// Notify `report` on the input characteristic of `kind`, if the service has one
+	async fn notify_report(&self, kind: Report, report: &impl Serialize) {
+		// Hosts in the boot protocol only know the keyboard
+		let handle = if self.boot_protocol.load(Ordering::Relaxed) {
+			self.boot_keyboard_input.filter(|_| kind == Report::KeyboardReport)
+		} else {
+			self.inputs
+				.iter()
+				.find(|(input, _)| *input == kind)
+				.map(|&(_, handle)| handle)
+		};
+		let Some(handle) = handle else {
+			return;
+		};
+
+		if config::BLE_HID.require_encryption && !ENCRYPTED.load(Ordering::Relaxed) {
+			debug!("Holding the report back until the link is encrypted");
+			return;
+		}
+
+		// Keys can still wake a suspended host up, the rest waits for it
+		if self.suspended.load(Ordering::Relaxed)
+			&& !matches!(kind, Report::KeyboardReport | Report::MediaKeyboardReport)
+		{
+			return;
+		}
+
+		let Some(conn) = self.connection().await else {
+			info!("No active connection");
+			return;
+		};
+		self.activity.signal(());
 
-		match gatt_server::notify_value(&conn, self.input_keyboard, &report_bytes) {
+		let mut report_bytes = [0u8; REPORT_SIZE_MAX];
+		let len = serialize(&mut report_bytes, report).expect("Failed to serialize report");
+
+		match gatt_server::notify_value(&conn, handle, &report_bytes[..len]) {
 			Ok(_) => {},
 			Err(e) => warn!("Error sending BLE HID report: {:?}", e),
 		}
 	}
+
+	/// Let the connection skip `ble.idle_latency` events once no report went out for `ble.idle_after`
+	/// ms, and tighten it back up with the next report
+	pub async fn pace(&self, conn: &Connection) -> ! {
+		if config::BLE.idle_after == 0 {
+			pending().await
+		}
+		let idle_after = Duration::from_millis(config::BLE.idle_after);
+
+		loop {
+			while with_timeout(idle_after, self.activity.wait()).await.is_ok() {}
+			debug!("Idle, relaxing the connection");
+			if let Err(e) = conn.set_conn_params(conn_params(config::BLE.idle_latency)) {
+				warn!("Could not relax the connection: {:?}", e);
+			}
+
+			self.activity.wait().await;
+			debug!("Busy, tightening the connection");
+			if let Err(e) = conn.set_conn_params(conn_params(config::BLE.latency)) {
+				warn!("Could not tighten the connection: {:?}", e);
+			}
+		}
+	}
+
+	/// The host we're connected to, if any
+	pub async fn connection(&self) -> Option<Connection> {
+		let handle = (*self.active_conn_handle.lock().await)?;
+		Connection::from_handle(handle)
+	}
+}
+pub enum HIDServiceEvent {
+	/// The host wrote the LED output report of the keyboard
+	Leds(u8),
 }
-type HIDServiceEvent = ();
 
 impl Service for HIDService {
 	type Event = HIDServiceEvent;
 	fn on_write(&self, handle: u16, data: &[u8]) -> Option<Self::Event> {
 		info!("HIDService::on_write: handle: {:x}, data: {:?}", handle, data);
-		None
+
+		let &value = data.first()?;
+		if handle == self.protocol_mode {
+			// 0 is the boot protocol, 1 the report one
+			self.boot_protocol.store(value == 0, Ordering::Relaxed);
+			info!(
+				"Switched to the {} protocol",
+				if value == 0 { "boot" } else { "report" }
+			);
+			None
+		} else if handle == self.hid_control {
+			// 0 is Suspend, 1 Exit Suspend
+			self.suspended.store(value == 0, Ordering::Relaxed);
+			info!("Host {}", if value == 0 { "suspended" } else { "resumed" });
+			None
+		} else if Some(handle) == self.output_keyboard || Some(handle) == self.boot_keyboard_output {
+			Some(HIDServiceEvent::Leds(value))
+		} else {
+			None
+		}
 	}
 }
 
@@ -242,25 +494,25 @@ pub struct Server {
 impl Server {
 	pub fn init(&mut self) {
 		self.dis
-			.model_number_set(&String::try_from(env!("DEVICE_NAME")).unwrap())
+			.model_number_set(&String::try_from(config::BLE.model_number).unwrap())
 			.unwrap();
 		self.dis
-			.serial_number_set(&String::try_from(env!("DEVICE_SERIAL")).unwrap())
+			.serial_number_set(&String::try_from(config::BLE.serial_number).unwrap())
 			.unwrap();
 		self.dis
-			.manufacturer_name_set(&String::try_from("PubSubinator").unwrap())
+			.manufacturer_name_set(&String::try_from(config::BLE.manufacturer).unwrap())
 			.unwrap();
 		self.dis
 			.pnp_id_set(&PnPID {
-				vid_source: VidSource::UsbIF,
-				vendor_id: 0xC0DE,
-				product_id: 0xCAFE,
-				product_version: u16::from_str_radix(env!("DEVICE_VERSION").replace(".", "").as_str(), 16)
-					.expect("Device version is not a valid hex number"),
+				vid_source: VidSource::from_str(config::BLE.vendor_id_source).unwrap(),
+				vendor_id: config::BLE.vendor_id,
+				product_id: config::BLE.product_id,
+				product_version: config::BLE.product_version,
 			})
 			.unwrap();
 
-		self.bas.battery_level_set(&66).unwrap();
+		// Until a `battery` publisher says otherwise
+		self.bas.battery_level_set(&100).unwrap();
 	}
 }
 
@@ -268,19 +520,43 @@ pub struct BleHid<'a> {
 	pub softdevice: &'a Softdevice,
 	pub server: &'a Server,
 	pub channel:
-		Subscriber<'a, CriticalSectionRawMutex, ReactorEvent, PUBSUB_CAPACITY, PUBSUB_SUBSCRIBERS, PUBSUB_PUBLISHERS>,
+		Subscriber<'a, CriticalSectionRawMutex, EventEnvelope, PUBSUB_CAPACITY, PUBSUB_SUBSCRIBERS, PUBSUB_PUBLISHERS>,
+	/// Last `MouseButtons`, sent along with the movements
+	pub mouse_buttons: u8,
 }
 
 impl<'a> BleHid<'a> {
-	pub async fn connect(sd: &'a Softdevice, security_handler: &'static dyn SecurityHandler) -> Connection {
+	/// Advertise fast for `ble.fast_timeout` s, then slowly until a host connects or
+	/// `ble.advertising_timeout` s went by
+	pub async fn connect(sd: &'a Softdevice, bonder: &'static Bonder) -> Option<Connection> {
+		let tx_power = tx_power(config::BLE.tx_power);
+
+		// Only the host of the slot gets to connect while it's around
+		if let Some(peer) = bonder.peer() {
+			let config = peripheral::Config {
+				timeout: Some(DIRECTED_TIMEOUT),
+				tx_power,
+				..Default::default()
+			};
+			let adv = peripheral::ConnectableAdvertisement::NonscannableDirected { peer };
+
+			info!("advertising to {}...", peer);
+			match peripheral::advertise_pairable(sd, adv, &config, bonder).await {
+				Ok(conn) => return Some(conn),
+				// It might use a private address we can't aim at
+				Err(peripheral::AdvertiseError::Timeout) => info!("{} didn't show up", peer),
+				Err(e) => warn!("Directed advertising failed: {:?}", e),
+			}
+		}
+
 		let adv_data = LegacyAdvertisementBuilder::new()
 			.flags(&[Flag::GeneralDiscovery, Flag::LE_Only])
 			.services_16(
 				ServiceList::Incomplete,
 				&[ServiceUuid16::BATTERY, ServiceUuid16::HUMAN_INTERFACE_DEVICE],
 			)
-			.full_name(env!("DEVICE_NAME"))
-			.raw(AdvertisementDataType::APPEARANCE, &[0xC1, 0x03])
+			.full_name(config::BLE.name)
+			.raw(AdvertisementDataType::APPEARANCE, &config::BLE.appearance.to_le_bytes())
 			.build();
 
 		let scan_data = LegacyAdvertisementBuilder::new()
@@ -294,29 +570,62 @@ impl<'a> BleHid<'a> {
 			)
 			.build();
 
-		let config = peripheral::Config::default();
-		let adv = peripheral::ConnectableAdvertisement::ScannableUndirected {
+		let adv = || peripheral::ConnectableAdvertisement::ScannableUndirected {
 			adv_data: &adv_data,
 			scan_data: &scan_data,
 		};
 
-		info!("advertising...");
+		if config::BLE.fast_timeout > 0 {
+			let config = peripheral::Config {
+				interval: adv_interval(config::BLE.fast_interval),
+				timeout: Some(config::BLE.fast_timeout * 100),
+				tx_power,
+				..Default::default()
+			};
+
+			info!("advertising fast...");
+			match peripheral::advertise_pairable(sd, adv(), &config, bonder).await {
+				Ok(conn) => return Some(conn),
+				Err(peripheral::AdvertiseError::Timeout) => {},
+				Err(e) => warn!("Fast advertising failed: {:?}", e),
+			}
+		}
 
-		let conn = peripheral::advertise_pairable(sd, adv, &config, security_handler)
-			.await
-			.unwrap();
-		info!("Updating active connection handle");
+		let config = peripheral::Config {
+			interval: adv_interval(config::BLE.slow_interval),
+			tx_power,
+			..Default::default()
+		};
+
+		info!("advertising...");
+		let advertise = peripheral::advertise_pairable(sd, adv(), &config, bonder);
+		let conn = match config::BLE.advertising_timeout {
+			0 => advertise.await,
+			timeout => with_timeout(Duration::from_secs(timeout.into()), advertise)
+				.await
+				.ok()?,
+		};
 
 		info!("advertising done!");
 
-		conn
+		Some(conn.unwrap())
 	}
 }
 
 impl<'a> RSubscriber for BleHid<'a> {
-	fn push(&mut self, value: ReactorEvent) -> Pin<Box<dyn Future<Output = ()> + '_>> {
+	// A stalled notification shouldn't hold back USB
+	fn overflow_policy(&self) -> OverflowPolicy {
+		OverflowPolicy::DropOldest
+	}
+
+	fn push(&mut self, value: EventEnvelope) -> Pin<Box<dyn Future<Output = ()> + '_>> {
 		Box::pin(async move {
-			match value {
+			// Typing on a board that gave up advertising starts it again
+			if let ReactorEvent::Key(KeyEvent::Pressed(_)) = value.event {
+				ADVERTISE.signal(());
+			}
+
+			match value.event {
 				ReactorEvent::KeyboardReport { modifier, keycodes } => {
 					let report = KeyboardReport {
 						modifier: modifier.into(),
@@ -333,40 +642,469 @@ impl<'a> RSubscriber for BleHid<'a> {
 						],
 					};
 
-					self.server.hid.send_report(&report).await;
+					self.server.hid.send_report(Report::KeyboardReport, &report).await;
+				},
+				// A pairing waiting for its passkey gets the keys first
+				ReactorEvent::Key(KeyEvent::Pressed(key)) if type_passkey(key) => {},
+				ReactorEvent::Key(KeyEvent::Released(key)) if release_passkey_key(key) => {},
+				ReactorEvent::Key(KeyEvent::Pressed(key)) =>
+					if let Some(usage_id) = consumer_usage(key) {
+						let report = MediaKeyboardReport { usage_id };
+						self.server.hid.send_report(Report::MediaKeyboardReport, &report).await;
+					},
+				// Only one media key is reported at a time, so releasing any clears it
+				ReactorEvent::Key(KeyEvent::Released(key)) if consumer_usage(key).is_some() => {
+					let report = MediaKeyboardReport { usage_id: 0 };
+					self.server.hid.send_report(Report::MediaKeyboardReport, &report).await;
 				},
+				ReactorEvent::Mouse { x, y } => {
+					// Movements are signed, as far as an `i8` goes
+					let delta = |value: u32| (value as i32).clamp(i8::MIN.into(), i8::MAX.into()) as i8;
+					let report = MouseReport {
+						buttons: self.mouse_buttons,
+						x: delta(x),
+						y: delta(y),
+						wheel: 0,
+						pan: 0,
+					};
+					self.server.hid.send_report(Report::MouseReport, &report).await;
+				},
+				ReactorEvent::MouseButtons(buttons) => {
+					// Kept for the movements, so dragging keeps the buttons down
+					self.mouse_buttons = buttons;
+					let report = MouseReport {
+						buttons,
+						x: 0,
+						y: 0,
+						wheel: 0,
+						pan: 0,
+					};
+					self.server.hid.send_report(Report::MouseReport, &report).await;
+				},
+				ReactorEvent::Joystick6DoF { x, y, z, rx, ry, rz } => {
+					let report = SpaceMouseReport {
+						x,
+						y,
+						z,
+						rx,
+						ry,
+						rz,
+						buttons: 0,
+					};
+					self.server.hid.send_report(Report::SpaceMouseReport, &report).await;
+				},
+				// Keys held when BLE was dropped would stay stuck on the host
+				ReactorEvent::Output { ble: false, .. } if OUTPUT.take_release(Transport::Ble) =>
+					self.server.hid.release_all().await,
+				ReactorEvent::Battery(level) => {
+					// Read by hosts that connect later, notified to the current one
+					if let Err(e) = self.server.bas.battery_level_set(&level) {
+						warn!("Error setting the battery level: {:?}", e);
+					}
+					if let Some(conn) = self.server.hid.connection().await {
+						if let Err(e) = self.server.bas.battery_level_notify(&conn, &level) {
+							warn!("Error notifying the battery level: {:?}", e);
+						}
+					}
+				},
+				ReactorEvent::Internal(
+					action @ (InternalEvent::BLENext
+					| InternalEvent::BLEPrev
+					| InternalEvent::BLEChange(_)
+					| InternalEvent::BLEClear
+					| InternalEvent::BLEClearAll),
+				) => SLOT_ACTIONS.send(action).await,
 				_ => {},
 			}
 		})
 	}
 }
 
+/// Hosts the board can be bonded with, switched between with the `BLE*` internal events
+pub const BOND_SLOTS: usize = 5;
+// Slots are a single digit in the keys, so the keys sort in slot order
+const _: () = assert!(BOND_SLOTS <= 10);
+
+/// Key of the selected slot in the database, after all the `ble/bond/...` ones
+const SLOT_KEY: &[u8] = b"ble/slot";
+/// What the SoftDevice needs for the CCCDs of our services
+const SYS_ATTRS_SIZE: usize = 62;
+/// How long to advertise to the host of the slot alone before anyone can connect, in 10 ms units
+const DIRECTED_TIMEOUT: u16 = 500;
+
+/// Slot actions of the keymap, for `ble_hid_task`
+static SLOT_ACTIONS: Channel<CriticalSectionRawMutex, InternalEvent, 4> = Channel::new();
+/// Raised by key presses, for `ble_hid_task` to advertise again once it gave up
+static ADVERTISE: Signal<CriticalSectionRawMutex, ()> = Signal::new();
+/// Whether the link to the host is encrypted, for `ble_hid.require_encryption`
+static ENCRYPTED: AtomicBool = AtomicBool::new(false);
+/// Pairing waiting for the passkey the host shows to be typed on the board
+static PASSKEY_ENTRY: BlockingMutex<CriticalSectionRawMutex, RefCell<Option<PasskeyEntry>>> =
+	BlockingMutex::new(RefCell::new(None));
+/// Keys the passkey entry took that are still down, the reports stay held until they're released
+static PASSKEY_KEYS: BlockingMutex<CriticalSectionRawMutex, RefCell<heapless::Vec<KeyCode, 8>>> =
+	BlockingMutex::new(RefCell::new(heapless::Vec::new()));
+/// Digit keys of the number row and of the keypad, from 0
+const DIGIT_KEYS: [(KeyCode, KeyCode); 10] = [
+	(KeyCode::Kb0, KeyCode::Kp0),
+	(KeyCode::Kb1, KeyCode::Kp1),
+	(KeyCode::Kb2, KeyCode::Kp2),
+	(KeyCode::Kb3, KeyCode::Kp3),
+	(KeyCode::Kb4, KeyCode::Kp4),
+	(KeyCode::Kb5, KeyCode::Kp5),
+	(KeyCode::Kb6, KeyCode::Kp6),
+	(KeyCode::Kb7, KeyCode::Kp7),
+	(KeyCode::Kb8, KeyCode::Kp8),
+	(KeyCode::Kb9, KeyCode::Kp9),
+];
+
+struct PasskeyEntry {
+	reply: PasskeyReply,
+	/// ASCII, like the SoftDevice wants them
+	digits: heapless::Vec<u8, 6>,
+}
+
+/// Feed a pressed key to the passkey being typed, returning whether it took it. Digits are typed in,
+/// Backspace takes the last one back, Enter sends all 6 and Escape gives up on pairing.
+fn type_passkey(key: KeyCode) -> bool {
+	PASSKEY_ENTRY.lock(|entry| {
+		let mut entry = entry.borrow_mut();
+		let Some(pending) = entry.as_mut() else {
+			return false;
+		};
+
+		match key {
+			KeyCode::BSpace => {
+				pending.digits.pop();
+			},
+			KeyCode::Enter | KeyCode::KpEnter if pending.digits.is_full() => {
+				let pending = entry.take().unwrap();
+				let mut passkey = [0u8; 6];
+				passkey.copy_from_slice(&pending.digits);
+				if let Err(e) = pending.reply.reply(Some(&passkey)) {
+					warn!("Could not send the passkey: {:?}", e);
+				}
+			},
+			KeyCode::Escape => {
+				info!("Passkey entry cancelled");
+				if let Err(e) = entry.take().unwrap().reply.reply(None) {
+					warn!("Could not cancel pairing: {:?}", e);
+				}
+			},
+			_ =>
+				if let Some(digit) = DIGIT_KEYS.iter().position(|&(kb, kp)| key == kb || key == kp) {
+					pending.digits.push(b'0' + digit as u8).ok();
+				},
+		}
+
+		// Enter ends the entry on the press, its release mustn't reach the host either
+		PASSKEY_KEYS.lock(|keys| keys.borrow_mut().push(key).ok());
+		true
+	})
+}
+
+/// Feed a released key to the passkey entry, returning whether it was one of its keys
+fn release_passkey_key(key: KeyCode) -> bool {
+	let taken = PASSKEY_KEYS.lock(|keys| {
+		let mut keys = keys.borrow_mut();
+		let taken = keys.contains(&key);
+		keys.retain(|&down| down != key);
+		taken
+	});
+	release_reports();
+	taken
+}
+
+/// Let the reports go again once the passkey entry is over and its keys are up
+fn release_reports() {
+	let done =
+		PASSKEY_ENTRY.lock(|entry| entry.borrow().is_none()) && PASSKEY_KEYS.lock(|keys| keys.borrow().is_empty());
+	if done {
+		OUTPUT.hold(false);
+	}
+}
+
+/// Advertising interval of `[ble]` in the 0.625 ms units of the SoftDevice
+fn adv_interval(ms: f32) -> u32 {
+	(ms / 0.625) as u32
+}
+
+/// Connection parameters of `[ble]`, skipping `latency` connection events
+pub(crate) fn conn_params(latency: u16) -> raw::ble_gap_conn_params_t {
+	raw::ble_gap_conn_params_t {
+		min_conn_interval: (config::BLE.min_interval / 1.25) as u16,
+		max_conn_interval: (config::BLE.max_interval / 1.25) as u16,
+		slave_latency: latency,
+		conn_sup_timeout: config::BLE.supervision_timeout / 10,
+	}
+}
+
+/// Connections inherit the TX power of the advertising that started them
+pub(crate) fn tx_power(dbm: i8) -> TxPower {
+	match dbm {
+		-40 => TxPower::Minus40dBm,
+		-20 => TxPower::Minus20dBm,
+		-16 => TxPower::Minus16dBm,
+		-12 => TxPower::Minus12dBm,
+		-8 => TxPower::Minus8dBm,
+		-4 => TxPower::Minus4dBm,
+		2 => TxPower::Plus2dBm,
+		3 => TxPower::Plus3dBm,
+		4 => TxPower::Plus4dBm,
+		5 => TxPower::Plus5dBm,
+		6 => TxPower::Plus6dBm,
+		7 => TxPower::Plus7dBm,
+		8 => TxPower::Plus8dBm,
+		_ => TxPower::ZerodBm,
+	}
+}
+
+/// Key of the `name` record of the bond in `slot`, `ble/bond/<slot>/<name>`
+fn bond_key(slot: usize, name: &str) -> String<24> {
+	let mut key = String::new();
+	write!(key, "ble/bond/{}/{}", slot, name).unwrap();
+	key
+}
+
 #[derive(Debug, Clone, Copy)]
-struct Peer {
-	master_id: MasterId,
-	key: EncryptionInfo,
-	peer_id: IdentityKey,
+pub(crate) struct Peer {
+	pub master_id: MasterId,
+	pub key: EncryptionInfo,
+	pub peer_id: IdentityKey,
 }
 
-pub struct Bonder {
+/// Version of the stored `Peer` layout, bumped whenever the fields below change
+const PEER_FORMAT: u8 = 1;
+/// Format, ediv, rand, ltk, flags, irk, address type and address
+pub(crate) const PEER_SIZE: usize = 1 + 2 + 8 + 16 + 1 + 16 + 1 + 6;
+
+impl Peer {
+	pub fn to_bytes(&self) -> [u8; PEER_SIZE] {
+		let mut bytes = [0u8; PEER_SIZE];
+		bytes[0] = PEER_FORMAT;
+		bytes[1..3].copy_from_slice(&self.master_id.ediv.to_le_bytes());
+		bytes[3..11].copy_from_slice(&self.master_id.rand);
+		bytes[11..27].copy_from_slice(&self.key.ltk);
+		bytes[27] = self.key.flags;
+		bytes[28..44].copy_from_slice(&self.peer_id.irk.as_raw().irk);
+		bytes[44] = self.peer_id.addr.address_type() as u8;
+		bytes[45..51].copy_from_slice(&self.peer_id.addr.bytes());
+		bytes
+	}
+
+	pub fn from_bytes(bytes: &[u8]) -> Option<Self> {
+		// Anything else was written by an older build, the host has to pair again
+		if bytes.len() != PEER_SIZE || bytes[0] != PEER_FORMAT {
+			return None;
+		}
+
+		let address_type = AddressType::try_from(bytes[44]).ok()?;
+		Some(Self {
+			master_id: MasterId {
+				ediv: u16::from_le_bytes(bytes[1..3].try_into().unwrap()),
+				rand: bytes[3..11].try_into().unwrap(),
+			},
+			key: EncryptionInfo {
+				ltk: bytes[11..27].try_into().unwrap(),
+				flags: bytes[27],
+			},
+			peer_id: IdentityKey {
+				irk: IdentityResolutionKey::from_raw(raw::ble_gap_irk_t {
+					irk: bytes[28..44].try_into().unwrap(),
+				}),
+				addr: Address::new(address_type, bytes[45..51].try_into().unwrap()),
+			},
+		})
+	}
+}
+
+/// A host the board is bonded with
+#[derive(Default)]
+struct Slot {
 	peer: Cell<Option<Peer>>,
-	sys_attrs: RefCell<Vec<u8>>,
-	db: &'static mut Database<&'static mut crate::Flash, CriticalSectionRawMutex>,
+	sys_attrs: RefCell<heapless::Vec<u8, SYS_ATTRS_SIZE>>,
+}
+
+impl Slot {
+	fn clear(&self) {
+		self.peer.set(None);
+		self.sys_attrs.borrow_mut().clear();
+	}
+}
+
+/// Keeps the bonds in RAM for the SoftDevice callbacks, which can't wait on the flash, and in the
+/// database for the next boot. The SoftDevice only gets to see the bond of the selected slot.
+pub struct Bonder {
+	slots: [Slot; BOND_SLOTS],
+	selected: Cell<usize>,
+	db: &'static Database<&'static mut crate::Flash, CriticalSectionRawMutex>,
+	/// Slots that changed since they were last written to `db`, one bit each
+	dirty: Cell<u32>,
+	/// Raised when a slot or the selection changed and has to be written to `db`
+	changed: Signal<CriticalSectionRawMutex, ()>,
+	/// A passkey was published for the board to show
+	showing_passkey: Cell<bool>,
 }
 
 impl Bonder {
-	pub fn new(db: &'static mut Database<&'static mut crate::Flash, CriticalSectionRawMutex>) -> Self {
-		Bonder {
-			peer: Cell::new(None),
-			sys_attrs: Default::default(),
+	/// Restore the bonds and the selected slot of the database
+	pub async fn new(db: &'static Database<&'static mut crate::Flash, CriticalSectionRawMutex>) -> Self {
+		let bonder = Bonder {
+			slots: Default::default(),
+			selected: Cell::new(0),
 			db,
+			dirty: Cell::new(0),
+			changed: Signal::new(),
+			showing_passkey: Cell::new(false),
+		};
+
+		let mut rtx = db.read_transaction().await;
+		for (index, slot) in bonder.slots.iter().enumerate() {
+			let mut buf = [0u8; PEER_SIZE];
+			if let Ok(len) = rtx.read(bond_key(index, "peer").as_bytes(), &mut buf).await {
+				slot.peer.set(Peer::from_bytes(&buf[..len]));
+			}
+			let mut buf = [0u8; SYS_ATTRS_SIZE];
+			if let Ok(len) = rtx.read(bond_key(index, "sys_attrs").as_bytes(), &mut buf).await {
+				slot.sys_attrs.borrow_mut().extend_from_slice(&buf[..len]).unwrap();
+			}
+
+			if let Some(peer) = slot.peer.get() {
+				info!("Restored the bond of slot {} with {}", index, peer.peer_id.addr);
+			}
+		}
+		let mut buf = [0u8; 1];
+		if let Ok(1) = rtx.read(SLOT_KEY, &mut buf).await {
+			bonder.selected.set((buf[0] as usize).min(BOND_SLOTS - 1));
+		}
+		drop(rtx);
+
+		info!("Using BLE slot {}", bonder.selected.get());
+
+		bonder
+	}
+
+	fn slot(&self) -> &Slot {
+		&self.slots[self.selected.get()]
+	}
+
+	/// Address of the host of the selected slot, if it has one
+	fn peer(&self) -> Option<Address> {
+		self.slot().peer.get().map(|peer| peer.peer_id.addr)
+	}
+
+	/// Have the slots of `dirty` (and the selection) written to the database
+	fn mark(&self, dirty: u32) {
+		self.dirty.set(self.dirty.get() | dirty);
+		self.changed.signal(());
+	}
+
+	/// Drop what's left of a pairing, the passkey shown or being typed
+	fn end_pairing(&self) {
+		// Only left there when the connection went away, and the SoftDevice with it
+		PASSKEY_ENTRY.lock(|entry| entry.borrow_mut().take());
+		release_reports();
+
+		if self.showing_passkey.replace(false) {
+			CHANNEL
+				.immediate_publisher()
+				.publish_immediate(EventEnvelope::new(ReactorEvent::Passkey(None), BLE_HID_SOURCE));
+		}
+	}
+
+	fn select(&self, slot: usize) -> bool {
+		if slot == self.selected.get() {
+			return false;
+		}
+
+		info!("Switching to BLE slot {}", slot);
+		self.selected.set(slot);
+		self.mark(0);
+		true
+	}
+
+	/// Apply a slot action, returning whether the current host has to be dropped
+	fn apply(&self, action: InternalEvent) -> bool {
+		let selected = self.selected.get();
+		match action {
+			InternalEvent::BLENext => self.select((selected + 1) % BOND_SLOTS),
+			InternalEvent::BLEPrev => self.select((selected + BOND_SLOTS - 1) % BOND_SLOTS),
+			InternalEvent::BLEChange(slot) if slot < BOND_SLOTS => self.select(slot),
+			InternalEvent::BLEChange(slot) => {
+				warn!("There's no BLE slot {}", slot);
+				false
+			},
+			InternalEvent::BLEClear => {
+				info!("Forgetting the host of BLE slot {}", selected);
+				self.slot().clear();
+				self.mark(1 << selected);
+				true
+			},
+			InternalEvent::BLEClearAll => {
+				info!("Forgetting the hosts of all the BLE slots");
+				self.slots.iter().for_each(Slot::clear);
+				self.mark((1 << BOND_SLOTS) - 1);
+				true
+			},
+			_ => false,
+		}
+	}
+
+	/// Wait for a slot action of the keymap that changes the host to talk to
+	pub async fn switched(&self) {
+		while !self.apply(SLOT_ACTIONS.receive().await) {}
+	}
+
+	/// Write the slots to the database whenever they change
+	pub async fn persist(&self) -> ! {
+		loop {
+			self.changed.wait().await;
+			let dirty = self.dirty.replace(0);
+
+			let mut wtx = self.db.write_transaction().await;
+			// Keys have to be written in order, which is the slot order
+			let written = async {
+				for (index, slot) in self.slots.iter().enumerate() {
+					if dirty & 1 << index == 0 {
+						continue;
+					}
+
+					// Copied out as the callbacks can change them while the flash is busy
+					let sys_attrs = slot.sys_attrs.borrow().clone();
+					match slot.peer.get() {
+						Some(peer) => {
+							wtx.write(bond_key(index, "peer").as_bytes(), &peer.to_bytes()).await?;
+							wtx.write(bond_key(index, "sys_attrs").as_bytes(), &sys_attrs).await?;
+						},
+						None => {
+							wtx.delete(bond_key(index, "peer").as_bytes()).await?;
+							wtx.delete(bond_key(index, "sys_attrs").as_bytes()).await?;
+						},
+					}
+				}
+				wtx.write(SLOT_KEY, &[self.selected.get() as u8]).await
+			}
+			.await;
+
+			match written {
+				Ok(()) => match wtx.commit().await {
+					Ok(()) => debug!("Stored the BLE slots"),
+					Err(e) => error!("Could not store the BLE slots: {:?}", e),
+				},
+				Err(e) => error!("Could not store the BLE slots: {:?}", e),
+			}
 		}
 	}
 }
 
 impl SecurityHandler for Bonder {
-	fn io_capabilities(&self) -> nrf_softdevice::ble::security::IoCapabilities {
-		nrf_softdevice::ble::security::IoCapabilities::None
+	fn io_capabilities(&self) -> IoCapabilities {
+		match config::BLE_HID.io_capabilities {
+			"DisplayOnly" => IoCapabilities::DisplayOnly,
+			"KeyboardOnly" => IoCapabilities::KeyboardOnly,
+			"KeyboardDisplay" => IoCapabilities::KeyboardDisplay,
+			_ => IoCapabilities::None,
+		}
 	}
 
 	// fn can_recv_out_of_band(&self, _conn: &nrf_softdevice::ble::Connection) -> bool {
@@ -378,23 +1116,42 @@ impl SecurityHandler for Bonder {
 	}
 
 	fn display_passkey(&self, passkey: &[u8; 6]) {
-		info!("display_passkey {:?}", passkey);
+		// ASCII digits
+		let passkey = passkey
+			.iter()
+			.fold(0, |passkey, digit| passkey * 10 + (digit - b'0') as u32);
+		info!("Passkey to type on the host: {}", passkey);
+
+		self.showing_passkey.set(true);
+		CHANNEL
+			.immediate_publisher()
+			.publish_immediate(EventEnvelope::new(ReactorEvent::Passkey(Some(passkey)), BLE_HID_SOURCE));
 	}
 
-	// fn enter_passkey(&self, _reply: nrf_softdevice::ble::PasskeyReply) {
-	// 	info!("enter_passkey");
-	// }
+	fn enter_passkey(&self, reply: PasskeyReply) {
+		info!("Type the passkey the host shows, then Enter");
+		PASSKEY_ENTRY.lock(|entry| {
+			*entry.borrow_mut() = Some(PasskeyEntry {
+				reply,
+				digits: heapless::Vec::new(),
+			})
+		});
+		// Neither the digits nor Enter are for the host, on any transport
+		OUTPUT.hold(true);
+	}
 
 	// fn recv_out_of_band(&self, _reply: nrf_softdevice::ble::OutOfBandReply) {
 	// 	info!("recv_out_of_band");
 	// }
 
-	fn on_security_update(
-		&self,
-		_conn: &nrf_softdevice::ble::Connection,
-		security_mode: nrf_softdevice::ble::SecurityMode,
-	) {
+	fn on_security_update(&self, _conn: &nrf_softdevice::ble::Connection, security_mode: SecurityMode) {
 		info!("on_security_update {:?}", security_mode);
+
+		ENCRYPTED.store(
+			!matches!(security_mode, SecurityMode::NoAccess | SecurityMode::Open),
+			Ordering::Relaxed,
+		);
+		self.end_pairing();
 	}
 
 	fn on_bonded(
@@ -404,22 +1161,37 @@ impl SecurityHandler for Bonder {
 		key: nrf_softdevice::ble::EncryptionInfo,
 		peer_id: nrf_softdevice::ble::IdentityKey,
 	) {
-		info!("on_bonded");
+		let selected = self.selected.get();
+		info!("on_bonded on slot {}", selected);
+
+		// A host only keeps the slot it last paired on
+		let mut dirty = 1 << selected;
+		for (index, slot) in self.slots.iter().enumerate() {
+			let same_host = slot
+				.peer
+				.get()
+				.map_or(false, |peer| peer.peer_id.is_match(peer_id.addr));
+			if index != selected && same_host {
+				slot.clear();
+				dirty |= 1 << index;
+			}
+		}
 
-		// In a real application you would want to signal another task to permanently store the keys in non-volatile memory here.
-		self.sys_attrs.borrow_mut().clear();
-		let peer = Peer {
+		let slot = self.slot();
+		slot.sys_attrs.borrow_mut().clear();
+		slot.peer.set(Some(Peer {
 			master_id,
 			key,
 			peer_id,
-		};
-		self.peer.set(Some(peer));
+		}));
+		self.mark(dirty);
 	}
 
 	fn get_key(&self, _conn: &nrf_softdevice::ble::Connection, master_id: MasterId) -> Option<EncryptionInfo> {
 		debug!("getting bond for: id: {}", master_id);
 
-		self.peer
+		self.slot()
+			.peer
 			.get()
 			.and_then(|peer| (master_id == peer.master_id).then_some(peer.key))
 	}
@@ -427,14 +1199,19 @@ impl SecurityHandler for Bonder {
 	fn save_sys_attrs(&self, conn: &nrf_softdevice::ble::Connection) {
 		debug!("saving system attributes for: {}", conn.peer_address());
 
-		if let Some(peer) = self.peer.get() {
+		let slot = self.slot();
+		if let Some(peer) = slot.peer.get() {
 			if peer.peer_id.is_match(conn.peer_address()) {
-				let mut sys_attrs = self.sys_attrs.borrow_mut();
-				let capacity = sys_attrs.capacity();
-				sys_attrs.resize(capacity, 0);
-				if let Ok(len) = gatt_server::get_sys_attrs(conn, &mut sys_attrs) {
-					sys_attrs.truncate(len);
-					// In a real application you would want to signal another task to permanently store sys_attrs for this connection's peer
+				// Keep the stored attributes if the SoftDevice can't hand out the new ones
+				let mut buf = [0u8; SYS_ATTRS_SIZE];
+				match gatt_server::get_sys_attrs(conn, &mut buf) {
+					Ok(len) => {
+						let mut sys_attrs = slot.sys_attrs.borrow_mut();
+						sys_attrs.clear();
+						sys_attrs.extend_from_slice(&buf[..len]).unwrap();
+						self.mark(1 << self.selected.get());
+					},
+					Err(e) => warn!("Could not get the system attributes: {:?}", e),
 				}
 			}
 		}
@@ -444,14 +1221,20 @@ impl SecurityHandler for Bonder {
 		let addr = conn.peer_address();
 		debug!("loading system attributes for: {}", addr);
 
-		let attrs = self.sys_attrs.borrow();
-		// In a real application you would search all stored peers to find a match
-		let attrs = if self.peer.get().map(|peer| peer.peer_id.is_match(addr)).unwrap_or(false) {
+		let slot = self.slot();
+		let attrs = slot.sys_attrs.borrow();
+		let attrs = if slot.peer.get().map(|peer| peer.peer_id.is_match(addr)).unwrap_or(false) {
 			(!attrs.is_empty()).then_some(attrs.as_slice())
 		} else {
 			None
 		};
 
-		gatt_server::set_sys_attrs(conn, attrs).unwrap();
+		if let Err(e) = gatt_server::set_sys_attrs(conn, attrs) {
+			// Stale attributes, e.g. of an older GATT table - start the host over from the defaults
+			warn!("Could not set the stored system attributes: {:?}", e);
+			if let Err(e) = gatt_server::set_sys_attrs(conn, None) {
+				error!("Could not set the default system attributes: {:?}", e);
+			}
+		}
 	}
 }
@@ -1,23 +1,31 @@
-use alloc::format;
 // We need to use alloc Vec, otherwise we can instatiate the KeymapConfig
 // without generics
 use alloc::vec::Vec;
 use core::str::FromStr;
+use embassy_time::Duration;
+use reactor::keymap::Keymap;
 use reactor::*;
 
-use crate::gpio::{Drive, Input, Level, Output, Pull};
-use crate::keymap_mid::*;
-use crate::matrix::{Matrix, MatrixDirection};
+use crate::analog_nrf::ANALOG_PERIOD;
+use crate::gpio::{Drive, Input, Level, Output, PinSpec, Pull};
+use crate::matrix::{Matrix, MatrixDirection, MATRIX_IDLE_AFTER, MATRIX_IDLE_PERIOD, MATRIX_PERIOD};
+use crate::output::OUTPUT;
 
 pub trait ConfigBuilder {
 	type Output;
 	fn build(&self) -> Self::Output;
 }
 
+/// Pins of the generated config were validated by the build script
+fn pin(pin: &str) -> PinSpec {
+	PinSpec::from_str(pin).unwrap()
+}
+
 #[derive(Debug, Default)]
 pub struct KeymapConfig {
 	pub layers: Vec<Vec<Vec<&'static str>>>,
-	pub hold_cycles: u16,
+	/// Time in ms a key has to be held to count as held
+	pub hold_time: u64,
 }
 
 impl ConfigBuilder for KeymapConfig {
@@ -31,22 +39,40 @@ impl ConfigBuilder for KeymapConfig {
 					.iter()
 					.map(|row| {
 						row.iter()
-							.map(|key| KeyCodeInt::Key(KeyCode::from_str(key).unwrap()))
+							.map(|key| KeyCodeInt::from_str(key).unwrap())
 							.collect::<Vec<KeyCodeInt>>()
 					})
 					.collect::<Vec<Vec<KeyCodeInt>>>()
 			})
 			.collect::<Vec<Vec<Vec<KeyCodeInt>>>>();
 
-		Keymap::new(layers, self.hold_cycles)
+		Keymap::new(layers, Duration::from_millis(self.hold_time)).with_output_toggle(|| OUTPUT.toggle())
 	}
 }
 
-#[derive(Debug, Default)]
+#[derive(Debug)]
 pub struct MatrixConfig {
 	pub inputs: Vec<MatrixConfigInputsType>,
 	pub outputs: Vec<MatrixConfigOutputsType>,
 	pub direction: &'static str,
+	/// Polling period in ms
+	pub period: u64,
+	/// Polling period in ms once idle for `idle_after` ms - 0 disables it
+	pub idle_period: u64,
+	pub idle_after: u64,
+}
+
+impl Default for MatrixConfig {
+	fn default() -> Self {
+		Self {
+			inputs: Vec::new(),
+			outputs: Vec::new(),
+			direction: "",
+			period: MATRIX_PERIOD,
+			idle_period: MATRIX_IDLE_PERIOD,
+			idle_after: MATRIX_IDLE_AFTER,
+		}
+	}
 }
 
 impl ConfigBuilder for MatrixConfig {
@@ -59,7 +85,17 @@ impl ConfigBuilder for MatrixConfig {
 			.map(|output| output.to_output())
 			.collect::<Vec<Output>>();
 
-		Matrix::new(inputs, outputs, MatrixDirection::from_str(self.direction).unwrap())
+		let matrix = Matrix::new(inputs, outputs, MatrixDirection::from_str(self.direction).unwrap())
+			.with_period(Duration::from_millis(self.period));
+
+		if self.idle_period > 0 {
+			matrix.with_idle_period(
+				Duration::from_millis(self.idle_period),
+				Duration::from_millis(self.idle_after),
+			)
+		} else {
+			matrix
+		}
 	}
 }
 
@@ -69,26 +105,9 @@ pub struct MatrixConfigInputsType {
 	pub pull: &'static str,
 }
 
-// TODO: Move the nrf-specific pin stuff to gpio.rs
 impl MatrixConfigInputsType {
 	fn to_input<'a>(self) -> Input<'a> {
-		let pull = Pull::from_str(self.pull).unwrap();
-
-		let parts = self.pin.split('.');
-		let (port, pin) = if let [port_str, pin_str] = parts.into_iter().collect::<Vec<&str>>().as_slice() {
-			let port = port_str
-				.parse::<u8>()
-				.expect(format!("Invalid port number for pin `{}`", self.pin).as_str());
-			let pin = pin_str
-				.parse::<u8>()
-				.expect(format!("Invalid pin number for pin `{}`", self.pin).as_str());
-			(port, pin)
-		} else {
-			panic!("Invalid pin format `{}`", self.pin)
-		};
-		let anypin = unsafe { embassy_nrf::gpio::AnyPin::steal(port * 32 + pin) };
-
-		embassy_nrf::gpio::Input::new(anypin, pull.into())
+		pin(self.pin).input(Pull::from_str(self.pull).unwrap())
 	}
 }
 
@@ -101,27 +120,130 @@ pub struct MatrixConfigOutputsType {
 
 impl MatrixConfigOutputsType {
 	fn to_output<'a>(self) -> Output<'a> {
-		let drive = Drive::from_str(self.drive).unwrap();
-		let level = Level::from_str(self.level).unwrap();
-
-		let parts = self.pin.split('.');
-		let (port, pin) = if let [port_str, pin_str] = parts.into_iter().collect::<Vec<&str>>().as_slice() {
-			let port = port_str
-				.parse::<u8>()
-				.expect(format!("Invalid port number for pin `{}`", self.pin).as_str());
-			let pin = pin_str
-				.parse::<u8>()
-				.expect(format!("Invalid pin number for pin `{}`", self.pin).as_str());
-			(port, pin)
-		} else {
-			panic!("Invalid pin format `{}`", self.pin)
-		};
-		let anypin = unsafe { embassy_nrf::gpio::AnyPin::steal(port * 32 + pin) };
-
-		embassy_nrf::gpio::Output::new(anypin, level.into(), drive.into())
+		pin(self.pin).output(
+			Level::from_str(self.level).unwrap(),
+			Drive::from_str(self.drive).unwrap(),
+		)
 	}
 }
 
+#[derive(Debug, Default)]
+pub struct UsbHidConfig {
+	/// Report descriptor of `report_maps` to expose - picked by `board_main!`
+	pub report: &'static str,
+}
+
+#[derive(Debug, Default)]
+pub struct BleHidConfig {
+	/// Names of `report_maps::Report` the BLE HID service exposes, given report IDs from 1 in order
+	pub reports: Vec<&'static str>,
+	/// What the board can show and take while pairing, one of `IoCapabilities`
+	pub io_capabilities: &'static str,
+	/// Hold reports back until the link is encrypted
+	pub require_encryption: bool,
+}
+
+/// How the `ble_central` publisher finds its device, with the defaults filled in by the build script
+#[derive(Debug, Default)]
+pub struct BleCentralConfig {
+	/// `XX:XX:XX:XX:XX:XX` of the only device to connect to
+	pub address: &'static str,
+	/// What the board can show while pairing, one of `IoCapabilities`
+	pub io_capabilities: &'static str,
+	/// Between losing the device and looking for it again, in ms
+	pub period: u64,
+	/// Publish the keys for the keymap, by usage, instead of as keycodes
+	pub keymap: bool,
+}
+
+#[derive(Debug, Default)]
+pub struct OutputConfig {
+	/// Name of the `output::OutputMode` the board starts in
+	pub mode: &'static str,
+}
+
+/// USB device descriptor, with the defaults filled in by the build script
+#[derive(Debug, Default)]
+pub struct UsbConfig {
+	pub vendor_id: u16,
+	pub product_id: u16,
+	pub manufacturer: &'static str,
+	pub product: &'static str,
+	pub serial_number: &'static str,
+	/// In mA
+	pub max_power: u16,
+	/// HID polling interval in ms
+	pub poll_interval: u8,
+	/// BCD, e.g. `0x0120` for 1.2.0
+	pub device_release: u16,
+}
+
+/// What the board advertises and puts in the device information service, with the defaults filled
+/// in by the build script
+#[derive(Debug, Default)]
+pub struct BleConfig {
+	pub name: &'static str,
+	pub manufacturer: &'static str,
+	pub model_number: &'static str,
+	pub serial_number: &'static str,
+	/// GAP appearance, e.g. `0x03C1` for a keyboard
+	pub appearance: u16,
+	/// Name of a `ble_hid::VidSource`
+	pub vendor_id_source: &'static str,
+	pub vendor_id: u16,
+	pub product_id: u16,
+	/// BCD, like the USB device release
+	pub product_version: u16,
+	/// Advertising interval in ms for the first `fast_timeout` s, then `slow_interval`
+	pub fast_interval: f32,
+	pub fast_timeout: u16,
+	pub slow_interval: f32,
+	/// In s, 0 to advertise until a host connects
+	pub advertising_timeout: u16,
+	/// In dBm, one of `TxPower`
+	pub tx_power: i8,
+	/// Preferred connection interval in ms
+	pub min_interval: f32,
+	pub max_interval: f32,
+	/// Connection events the board can skip while reports go out
+	pub latency: u16,
+	/// In ms
+	pub supervision_timeout: u16,
+	/// Connection events the board can skip once no report went out for `idle_after` ms
+	pub idle_latency: u16,
+	pub idle_after: u64,
+	pub att_mtu: u16,
+	/// In 1.25 ms units
+	pub event_length: u16,
+	pub peripheral_connections: u8,
+	pub central_connections: u8,
+}
+
+/// Flash the database lives on, with the defaults filled in by the build script
+#[derive(Debug, Default)]
+pub struct FlashConfig {
+	/// `qspi`, or `internal` for the `STORAGE` region of `memory.x` - the rest is for QSPI only
+	pub backend: &'static str,
+	pub sck: &'static str,
+	pub csn: &'static str,
+	pub io0: &'static str,
+	pub io1: &'static str,
+	pub io2: &'static str,
+	pub io3: &'static str,
+	/// In bytes
+	pub capacity: u32,
+	/// Name of a `qspi::ReadOpcode`
+	pub read_opcode: &'static str,
+	/// Name of a `qspi::WriteOpcode`
+	pub write_opcode: &'static str,
+	/// Name of a `qspi::Frequency`
+	pub frequency: &'static str,
+	/// Name of a `flash_nrf::QuadEnable`
+	pub quad_enable: &'static str,
+	/// What the chip has to answer to Read Identification (`0x9F`)
+	pub jedec_id: u32,
+}
+
 // #[derive(Debug, Clone, Default)]
 // pub struct HidConfig {
 // 	pub descriptors: Vec<&'static str>,
@@ -133,36 +255,40 @@ impl MatrixConfigOutputsType {
 // 	}
 // }
 
-// #[derive(Debug, Default)]
-// pub struct AnalogConfig {
-// 	pub inputs: Vec<&'static str>,
-// }
+// TODO: Build the Analog publisher from the config - the number of inputs is a const generic
+#[derive(Debug)]
+pub struct AnalogConfig {
+	pub inputs: Vec<&'static str>,
+	/// Polling period in ms
+	pub period: u64,
+}
 
-// impl ConfigBuilder for AnalogConfig {
-// 	type Output = Analog<'static, 3>;
-// 	fn build(&self) -> Self::Output {
-// 		fn to_analog_in(pin: &'static str) -> embassy_nrf::saadc::AnyInput {
-// 			let parts = pin.split('.');
-// 			let (port, pin) = if let [port_str, pin_str] = parts.into_iter().collect::<Vec<&str>>().as_slice() {
-// 				let port = port_str
-// 					.parse::<u8>()
-// 					.expect(format!("Invalid port number for pin `{}`", pin).as_str());
-// 				let pin = pin_str
-// 					.parse::<u8>()
-// 					.expect(format!("Invalid pin number for pin `{}`", pin).as_str());
-// 				(port, pin)
-// 			} else {
-// 				panic!("Invalid pin format `{}`", pin)
-// 			};
-// 			let anypin = unsafe { embassy_nrf::gpio::AnyPin::steal(port * 32 + pin) };
-
-// 			anypin.into()
-// 		}
-
-// 		let inputs = self.inputs.iter().map(|input| to_analog_in(&input)).collect::<Vec<embassy_nrf::saadc::AnyInput>>();
-// 		let inputs_slice = inputs.as_slice();
-
-// 		let p_saadc = unsafe { embassy_nrf::peripherals::SAADC::steal() };
-// 		Analog::new(p_saadc, inputs)
-// 	}
-// }
+impl Default for AnalogConfig {
+	fn default() -> Self {
+		Self {
+			inputs: Vec::new(),
+			period: ANALOG_PERIOD,
+		}
+	}
+}
+
+/// How the `battery` publisher measures the battery, with the defaults filled in by the build script
+#[derive(Debug, Default)]
+pub struct BatteryConfig {
+	/// `vddh`, `vdd` or an analog pin
+	pub input: &'static str,
+	/// Battery voltage over the voltage at `input`
+	pub divider: f32,
+	/// Discharge curve, from full to empty
+	pub curve: Vec<BatteryConfigCurveType>,
+	/// Polling period in ms
+	pub period: u64,
+	/// Charge in % under which the low battery hook runs
+	pub low: u8,
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct BatteryConfigCurveType {
+	pub mv: u32,
+	pub percent: u8,
+}
@@ -1,98 +1,179 @@
-use core::convert::Infallible;
+use core::str::FromStr;
 
 use defmt::*;
 use ekv::config;
 use ekv::flash::PageID;
 use embassy_nrf::{peripherals, qspi};
+use strum::EnumString;
+
+use crate::gpio::PinSpec;
 
 // Workaround for alignment requirements.
 #[repr(C, align(4))]
-struct AlignedBuf([u8; 2048]);
-
-static mut BUF: AlignedBuf = AlignedBuf([0; 2048]);
+struct AlignedBuf([u8; config::PAGE_SIZE]);
 
 pub struct Flash<'a> {
 	qspi: qspi::Qspi<'a, peripherals::QSPI>,
+	buf: AlignedBuf,
 }
 
 impl<'a> ekv::flash::Flash for Flash<'a> {
-	type Error = Infallible;
+	type Error = FlashError;
 
 	fn page_count(&self) -> usize {
-		config::MAX_PAGE_COUNT
+		(crate::config::FLASH.capacity as usize / config::PAGE_SIZE).min(config::MAX_PAGE_COUNT)
 	}
 
 	async fn erase(&mut self, page_id: PageID) -> Result<(), Self::Error> {
-		self.qspi
-			.erase((page_id.index() * config::PAGE_SIZE) as u32)
-			.await
-			.unwrap();
+		self.qspi.erase((page_id.index() * config::PAGE_SIZE) as u32).await?;
 		Ok(())
 	}
 
 	async fn read(&mut self, page_id: PageID, offset: usize, data: &mut [u8]) -> Result<(), Self::Error> {
 		let address = page_id.index() * config::PAGE_SIZE + offset;
-		unsafe {
-			self.qspi.read(address as u32, &mut BUF.0[..data.len()]).await.unwrap();
-			data.copy_from_slice(&BUF.0[..data.len()])
-		}
+		let buf = &mut self.buf.0[..data.len()];
+		self.qspi.read(address as u32, buf).await?;
+		data.copy_from_slice(buf);
 		Ok(())
 	}
 
 	async fn write(&mut self, page_id: PageID, offset: usize, data: &[u8]) -> Result<(), Self::Error> {
 		let address = page_id.index() * config::PAGE_SIZE + offset;
-		unsafe {
-			BUF.0[..data.len()].copy_from_slice(data);
-			self.qspi.write(address as u32, &BUF.0[..data.len()]).await.unwrap();
-		}
+		let buf = &mut self.buf.0[..data.len()];
+		buf.copy_from_slice(data);
+		self.qspi.write(address as u32, buf).await?;
 		Ok(())
 	}
 }
 
+/// Where the chip keeps its Quad Enable bit, which has to be set for the 4-line opcodes
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Format, EnumString)]
+pub enum QuadEnable {
+	/// No bit to set, or it ships set
+	None,
+	/// Status register bit 6, written with `0x01` - Macronix, ISSI
+	Sr1Bit6,
+	/// Status register 2 bit 1, read with `0x35` and written with `0x31` - Winbond, GigaDevice
+	Sr2Bit1,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Format)]
+pub enum FlashError {
+	/// Talking to the chip failed
+	Qspi,
+	/// The chip answered Read Identification with something else than `config::FLASH.jedec_id`
+	UnknownChip(u32),
+}
+
+impl From<qspi::Error> for FlashError {
+	fn from(_: qspi::Error) -> Self {
+		Self::Qspi
+	}
+}
+
+fn read_opcode(name: &str) -> qspi::ReadOpcode {
+	match name {
+		"FASTREAD" => qspi::ReadOpcode::FASTREAD,
+		"READ2O" => qspi::ReadOpcode::READ2O,
+		"READ2IO" => qspi::ReadOpcode::READ2IO,
+		"READ4O" => qspi::ReadOpcode::READ4O,
+		"READ4IO" => qspi::ReadOpcode::READ4IO,
+		_ => panic!("Unknown read opcode `{}`", name),
+	}
+}
+
+fn write_opcode(name: &str) -> qspi::WriteOpcode {
+	match name {
+		"PP" => qspi::WriteOpcode::PP,
+		"PP2O" => qspi::WriteOpcode::PP2O,
+		"PP4O" => qspi::WriteOpcode::PP4O,
+		"PP4IO" => qspi::WriteOpcode::PP4IO,
+		_ => panic!("Unknown write opcode `{}`", name),
+	}
+}
+
+fn frequency(name: &str) -> qspi::Frequency {
+	match name {
+		"M32" => qspi::Frequency::M32,
+		"M16" => qspi::Frequency::M16,
+		"M8" => qspi::Frequency::M8,
+		"M4" => qspi::Frequency::M4,
+		"M2" => qspi::Frequency::M2,
+		_ => panic!("Unknown frequency `{}`", name),
+	}
+}
+
 impl<'a> Flash<'a> {
-	pub async fn new() -> Self {
+	/// Set up the chip of `config::FLASH`, refusing to touch any other one
+	pub async fn new() -> Result<Self, FlashError> {
+		let flash = &*crate::config::FLASH;
 		let mut config = qspi::Config::default();
 
-		config.read_opcode = qspi::ReadOpcode::READ4IO;
-		config.write_opcode = qspi::WriteOpcode::PP4IO;
+		config.read_opcode = read_opcode(flash.read_opcode);
+		config.write_opcode = write_opcode(flash.write_opcode);
 		config.write_page_size = qspi::WritePageSize::_256BYTES;
-		config.frequency = qspi::Frequency::M32;
-		config.capacity = 4 * 1024 * 1024; // 4MB
+		config.frequency = frequency(flash.frequency);
+		config.capacity = flash.capacity;
 		config.deep_power_down = Some(qspi::DeepPowerDownConfig {
 			enter_time: 3, // tDP = 30uS
 			exit_time: 3,  // tRDP = 35uS
 		});
 
-		// TODO: Configure the QSPI pins per device
-		// Valid for nrf52840 DK and Particle Xenon
+		let pin = |pin: &str| PinSpec::from_str(pin).unwrap().steal();
 		let qspi = unsafe { peripherals::QSPI::steal() };
-		let sck = unsafe { peripherals::P0_19::steal() };
-		let csn = unsafe { peripherals::P0_17::steal() };
-		let io0 = unsafe { peripherals::P0_20::steal() };
-		let io1 = unsafe { peripherals::P0_21::steal() };
-		let io2 = unsafe { peripherals::P0_22::steal() };
-		let io3 = unsafe { peripherals::P0_23::steal() };
-
-		let mut q: qspi::Qspi<_> = qspi::Qspi::new(qspi, crate::Irqs, sck, csn, io0, io1, io2, io3, config);
-
-		let mut id = [1; 3];
-		q.custom_instruction(0x9F, &[], &mut id).await.unwrap();
-		info!("Initialized flash with ID: {:X}", id);
-
-		// Read status register
-		let mut status = [4; 1];
-		unwrap!(q.custom_instruction(0x05, &[], &mut status).await);
-
-		info!("Flash status: {:X}", status[0]);
-
-		if status[0] & 0x40 == 0 {
-			status[0] |= 0x40;
-
-			unwrap!(q.custom_instruction(0x01, &status, &mut []).await);
-
-			info!("Enabled Quad SPI Flash mode");
+		let mut q: qspi::Qspi<_> = qspi::Qspi::new(
+			qspi,
+			crate::Irqs,
+			pin(flash.sck),
+			pin(flash.csn),
+			pin(flash.io0),
+			pin(flash.io1),
+			pin(flash.io2),
+			pin(flash.io3),
+			config,
+		);
+
+		let mut id = [0; 3];
+		q.custom_instruction(0x9F, &[], &mut id).await?;
+		let jedec_id = u32::from_be_bytes([0, id[0], id[1], id[2]]);
+		if jedec_id != flash.jedec_id {
+			error!(
+				"Unknown flash chip with JEDEC ID {:06X}, expected {:06X} - set `flash.jedec_id` if it's the right one",
+				jedec_id, flash.jedec_id
+			);
+			return Err(FlashError::UnknownChip(jedec_id));
+		}
+		info!("Initialized flash with ID: {:06X}", jedec_id);
+
+		match QuadEnable::from_str(flash.quad_enable).unwrap() {
+			QuadEnable::None => {},
+			QuadEnable::Sr1Bit6 => {
+				let mut status = [0; 1];
+				q.custom_instruction(0x05, &[], &mut status).await?;
+				info!("Flash status: {:X}", status[0]);
+
+				if status[0] & 0x40 == 0 {
+					q.custom_instruction(0x06, &[], &mut []).await?;
+					q.custom_instruction(0x01, &[status[0] | 0x40], &mut []).await?;
+					info!("Enabled Quad SPI Flash mode");
+				}
+			},
+			QuadEnable::Sr2Bit1 => {
+				let mut status = [0; 1];
+				q.custom_instruction(0x35, &[], &mut status).await?;
+				info!("Flash status 2: {:X}", status[0]);
+
+				if status[0] & 0x02 == 0 {
+					q.custom_instruction(0x06, &[], &mut []).await?;
+					q.custom_instruction(0x31, &[status[0] | 0x02], &mut []).await?;
+					info!("Enabled Quad SPI Flash mode");
+				}
+			},
 		}
 
-		Self { qspi: q }
+		Ok(Self {
+			qspi: q,
+			buf: AlignedBuf([0; config::PAGE_SIZE]),
+		})
 	}
 }
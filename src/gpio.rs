@@ -1,5 +1,8 @@
 use strum::EnumString;
 
+mod pin;
+pub use pin::*;
+
 #[cfg(feature = "nrf")]
 pub type Input<'a> = embassy_nrf::gpio::Input<'a, embassy_nrf::gpio::AnyPin>;
 #[cfg(feature = "nrf")]
@@ -122,3 +125,19 @@ impl From<Drive> for embassy_nrf::gpio::OutputDrive {
 		}
 	}
 }
+
+#[cfg(feature = "nrf")]
+impl PinSpec {
+	/// Take the pin without its peripheral singleton - the board config hands out each pin once
+	pub fn steal(self) -> embassy_nrf::gpio::AnyPin {
+		unsafe { embassy_nrf::gpio::AnyPin::steal(self.index()) }
+	}
+
+	pub fn input<'a>(self, pull: Pull) -> Input<'a> {
+		embassy_nrf::gpio::Input::new(self.steal(), pull.into())
+	}
+
+	pub fn output<'a>(self, level: Level, drive: Drive) -> Output<'a> {
+		embassy_nrf::gpio::Output::new(self.steal(), level.into(), drive.into())
+	}
+}
@@ -0,0 +1,100 @@
+//! Pins as written in board configs - `0.04`, or like the peripherals, `P0_04`.
+//!
+//! Only depends on `core`: the build script and `board_main!` include this file as well, so a pin
+//! the build accepted is one the firmware can parse.
+
+use core::fmt;
+use core::str::FromStr;
+
+/// nRF52840 pins connected to the SAADC, `AIN0` to `AIN7`
+pub const ANALOG_PINS: [PinSpec; 8] = [
+	PinSpec::new(0, 2),
+	PinSpec::new(0, 3),
+	PinSpec::new(0, 4),
+	PinSpec::new(0, 5),
+	PinSpec::new(0, 28),
+	PinSpec::new(0, 29),
+	PinSpec::new(0, 30),
+	PinSpec::new(0, 31),
+];
+
+/// Pins the firmware always uses for something else - the QSPI flash ones depend on the board. USB's
+/// D+/D-/VBUS are dedicated pads, they don't even have a pin name.
+pub const RESERVED_PINS: [(PinSpec, &str); 3] = [
+	(PinSpec::new(0, 0), "the 32.768 kHz crystal"),
+	(PinSpec::new(0, 1), "the 32.768 kHz crystal"),
+	(PinSpec::new(0, 18), "reset"),
+];
+
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Ord, PartialOrd, Hash)]
+pub struct PinSpec {
+	pub port: u8,
+	pub pin: u8,
+}
+
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum PinError {
+	/// Neither `<port>.<pin>` nor `P<port>_<pin>`
+	Format,
+	/// Well-formed, but not a pin of the nRF52840
+	NoSuchPin,
+}
+
+impl fmt::Display for PinError {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		match self {
+			Self::Format => write!(f, "expected `<port>.<pin>` or `P<port>_<pin>`"),
+			Self::NoSuchPin => write!(f, "the nRF52840 has pins 0.00-0.31 and 1.00-1.15"),
+		}
+	}
+}
+
+impl PinSpec {
+	pub const fn new(port: u8, pin: u8) -> Self {
+		Self { port, pin }
+	}
+
+	/// Index across ports, as taken by `AnyPin::steal`
+	pub const fn index(self) -> u8 {
+		self.port * 32 + self.pin
+	}
+
+	pub fn is_analog(self) -> bool {
+		ANALOG_PINS.contains(&self)
+	}
+
+	/// What the pin is taken by, if the board can't use it
+	pub fn reserved(self) -> Option<&'static str> {
+		RESERVED_PINS
+			.iter()
+			.find(|(pin, _)| *pin == self)
+			.map(|(_, reason)| *reason)
+	}
+}
+
+impl FromStr for PinSpec {
+	type Err = PinError;
+
+	fn from_str(s: &str) -> Result<Self, Self::Err> {
+		let (port, pin) = match s.strip_prefix('P').or_else(|| s.strip_prefix('p')) {
+			Some(alias) => alias.split_once('_'),
+			None => s.split_once('.'),
+		}
+		.ok_or(PinError::Format)?;
+
+		let port = port.parse::<u8>().map_err(|_| PinError::Format)?;
+		let pin = pin.parse::<u8>().map_err(|_| PinError::Format)?;
+
+		match port {
+			0 if pin < 32 => Ok(Self::new(port, pin)),
+			1 if pin < 16 => Ok(Self::new(port, pin)),
+			_ => Err(PinError::NoSuchPin),
+		}
+	}
+}
+
+impl fmt::Display for PinSpec {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		write!(f, "{}.{:02}", self.port, self.pin)
+	}
+}
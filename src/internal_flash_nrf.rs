@@ -0,0 +1,124 @@
+//! `ekv` on the nRF52840's own flash, in the `STORAGE` region of `memory.x`, for boards without a
+//! QSPI chip. The NVMC can't be touched while the SoftDevice runs, so BLE builds go through the
+//! SoftDevice flash API instead.
+//!
+//! The database keeps the page size of the QSPI chips, which is smaller than what the NVMC erases,
+//! so each page gets an erase page of its own and the rest of it is left unused.
+
+use defmt::*;
+use ekv::config;
+use ekv::flash::PageID;
+#[cfg(not(feature = "ble"))]
+use embassy_nrf::{nvmc::Nvmc, peripherals};
+#[cfg(not(feature = "ble"))]
+use embedded_storage::nor_flash::{NorFlash, ReadNorFlash};
+#[cfg(feature = "ble")]
+use embedded_storage_async::nor_flash::{NorFlash, ReadNorFlash};
+#[cfg(feature = "ble")]
+use nrf_softdevice::Softdevice;
+
+/// Smallest erasable unit of the nRF52840 flash
+const ERASE_SIZE: usize = 4096;
+/// Room each `ekv` page takes, so erasing it can't take another one with it
+const PAGE_STRIDE: usize = config::PAGE_SIZE.div_ceil(ERASE_SIZE) * ERASE_SIZE;
+
+extern "C" {
+	// Set by `memory.x`
+	static __storage_start: u32;
+	static __storage_end: u32;
+}
+
+// The SoftDevice only writes from word-aligned RAM
+#[repr(C, align(4))]
+struct AlignedBuf([u8; config::PAGE_SIZE]);
+
+/// Error of the SoftDevice flash API
+#[cfg(feature = "ble")]
+pub type InternalFlashError = nrf_softdevice::FlashError;
+/// Error of the NVMC
+#[cfg(not(feature = "ble"))]
+pub type InternalFlashError = embassy_nrf::nvmc::Error;
+
+pub struct InternalFlash {
+	#[cfg(feature = "ble")]
+	flash: nrf_softdevice::Flash,
+	#[cfg(not(feature = "ble"))]
+	flash: Nvmc<'static>,
+	/// Address of the first page
+	start: u32,
+	page_count: usize,
+	buf: AlignedBuf,
+}
+
+impl InternalFlash {
+	pub fn new(#[cfg(feature = "ble")] sd: &Softdevice) -> Self {
+		let (start, end) = unsafe {
+			(
+				&__storage_start as *const u32 as u32,
+				&__storage_end as *const u32 as u32,
+			)
+		};
+
+		assert!(start as usize % ERASE_SIZE == 0);
+		let page_count = ((end - start) as usize / PAGE_STRIDE).min(config::MAX_PAGE_COUNT);
+
+		#[cfg(feature = "ble")]
+		let flash = nrf_softdevice::Flash::take(sd);
+		#[cfg(not(feature = "ble"))]
+		let flash = Nvmc::new(unsafe { peripherals::NVMC::steal() });
+
+		info!("Initialized internal flash at {:X} with {} pages", start, page_count);
+
+		Self {
+			flash,
+			start,
+			page_count,
+			buf: AlignedBuf([0; config::PAGE_SIZE]),
+		}
+	}
+
+	fn address(&self, page_id: PageID, offset: usize) -> u32 {
+		self.start + (page_id.index() * PAGE_STRIDE + offset) as u32
+	}
+}
+
+impl ekv::flash::Flash for InternalFlash {
+	type Error = InternalFlashError;
+
+	fn page_count(&self) -> usize {
+		self.page_count
+	}
+
+	async fn erase(&mut self, page_id: PageID) -> Result<(), Self::Error> {
+		let from = self.address(page_id, 0);
+		let to = from + PAGE_STRIDE as u32;
+
+		#[cfg(feature = "ble")]
+		self.flash.erase(from, to).await?;
+		#[cfg(not(feature = "ble"))]
+		self.flash.erase(from, to)?;
+		Ok(())
+	}
+
+	async fn read(&mut self, page_id: PageID, offset: usize, data: &mut [u8]) -> Result<(), Self::Error> {
+		let address = self.address(page_id, offset);
+
+		#[cfg(feature = "ble")]
+		self.flash.read(address, data).await?;
+		#[cfg(not(feature = "ble"))]
+		self.flash.read(address, data)?;
+		Ok(())
+	}
+
+	async fn write(&mut self, page_id: PageID, offset: usize, data: &[u8]) -> Result<(), Self::Error> {
+		let address = self.address(page_id, offset);
+		let buf = &mut self.buf.0[..data.len()];
+		buf.copy_from_slice(data);
+
+		#[cfg(feature = "ble")]
+		self.flash.write(address, buf).await?;
+		#[cfg(not(feature = "ble"))]
+		self.flash.write(address, buf)?;
+		Ok(())
+	}
+}
@@ -3,7 +3,7 @@ use core::pin::Pin;
 use alloc::boxed::Box;
 use futures::Future;
 use reactor::middleware::Middleware;
-use reactor::ReactorEvent;
+use reactor::{EventEnvelope, ReactorEvent};
 
 /// Translates three 2D joysticks into a 6DOF space mouse report
 /// Arranged in a triangle, with the first joystick at the top, and the other two at the bottom
@@ -31,10 +31,10 @@ pub fn calculate_triangular_6dof(j1x: i16, j1y: i16, j2x: i16, j2y: i16, j3x: i1
 pub struct Joystick6DOFMid();
 
 impl Middleware for Joystick6DOFMid {
-	fn process(&mut self, value: ReactorEvent) -> Pin<Box<dyn Future<Output = Option<ReactorEvent>> + '_>> {
+	fn process(&mut self, value: EventEnvelope) -> Pin<Box<dyn Future<Output = Option<EventEnvelope>> + '_>> {
 		Box::pin(async move {
-			match value {
-				ReactorEvent::Analog6Axis(j1x, j1y, j2x, j2y, j3x, j3y) => Some(calculate_triangular_6dof(j1x, j1y, j2x, j2y, j3x, j3y)),
+			match value.event {
+				ReactorEvent::Analog6Axis(j1x, j1y, j2x, j2y, j3x, j3y) => Some(value.derive(calculate_triangular_6dof(j1x, j1y, j2x, j2y, j3x, j3y))),
 				_ => None,
 			}
 		})
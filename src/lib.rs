@@ -5,22 +5,32 @@
 extern crate alloc;
 #[cfg(feature = "debug")]
 extern crate defmt_rtt;
+#[cfg(feature = "nrf")]
 extern crate embassy_nrf;
 extern crate panic_probe;
 
-use core::mem::{self, size_of};
+#[cfg(not(feature = "nrf"))]
+compile_error!("No MCU selected - only the `nrf52840` feature is supported for now");
+#[cfg(all(feature = "ble", feature = "critical-section-single-core"))]
+compile_error!("The SoftDevice already provides the critical section, disable `critical-section-single-core`");
+#[cfg(not(any(feature = "ble", feature = "critical-section-single-core")))]
+compile_error!("Boards without BLE need the `critical-section-single-core` feature");
+
+#[cfg(feature = "ble")]
+use core::mem;
+use core::mem::size_of;
 
 use defmt::*;
 
 use ekv::Database;
+#[cfg(feature = "ble")]
 use embassy_executor::task;
 use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
 use embassy_sync::pubsub::PubSubChannel;
-use embassy_time::{Duration, Ticker};
+#[cfg(feature = "usb")]
 use lazy_static::lazy_static;
-use matrix::MATRIX_PERIOD;
-use reactor::reactor_event::ReactorEvent;
-use reactor::Polled;
+use reactor::queue::Diagnostics;
+use reactor::reactor_event::{EventEnvelope, ReactorEvent};
 
 use embedded_alloc::Heap;
 
@@ -29,49 +39,101 @@ use embedded_alloc::Heap;
 static HEAP: Heap = Heap::empty();
 
 use embassy_nrf::interrupt::Priority;
+#[cfg(feature = "usb")]
+use embassy_nrf::usb;
+#[cfg(all(feature = "usb", not(feature = "ble")))]
+use embassy_nrf::usb::vbus_detect::HardwareVbusDetect;
+#[cfg(all(feature = "usb", feature = "ble"))]
 use embassy_nrf::usb::vbus_detect::SoftwareVbusDetect;
-use embassy_nrf::{bind_interrupts, pac, peripherals, qspi, rng, saadc, usb};
+use embassy_nrf::{bind_interrupts, pac, peripherals, qspi, rng, saadc};
+#[cfg(feature = "ble")]
 use nrf_softdevice::{raw, SocEvent, Softdevice};
 use static_cell::make_static;
 
 pub mod analog_nrf;
+pub mod battery_nrf;
+#[cfg(feature = "ble")]
+pub mod ble_central;
+#[cfg(feature = "ble")]
 pub mod ble_hid;
-pub mod config;
+pub mod config {
+	//! Sections of the board config, generated by the build script
+	include!(concat!(env!("OUT_DIR"), "/config.rs"));
+}
 pub mod config_types;
 pub mod data;
 pub mod flash_nrf;
 pub mod gpio;
-pub mod keyboard_report_mid;
-pub mod keymap_mid;
+pub mod internal_flash_nrf;
 pub mod matrix;
+#[cfg(feature = "usb")]
 pub mod nrf;
+pub mod output;
 pub mod prelude;
+pub mod trace;
+#[cfg(feature = "usb")]
 pub mod usb_hid;
 pub mod report_maps;
+pub mod storage;
 pub mod joystick_6dof_mid;
 
+#[cfg(all(feature = "usb", feature = "ble"))]
+bind_interrupts!(struct Irqs {
+	USBD => usb::InterruptHandler<peripherals::USBD>;
+	SAADC => saadc::InterruptHandler;
+	QSPI => qspi::InterruptHandler<peripherals::QSPI>;
+	RNG => rng::InterruptHandler<peripherals::RNG>;
+});
+
+// Without the SoftDevice, VBUS is detected through the POWER peripheral
+#[cfg(all(feature = "usb", not(feature = "ble")))]
 bind_interrupts!(struct Irqs {
 	USBD => usb::InterruptHandler<peripherals::USBD>;
+	POWER_CLOCK => usb::vbus_detect::InterruptHandler;
 	SAADC => saadc::InterruptHandler;
 	QSPI => qspi::InterruptHandler<peripherals::QSPI>;
 	RNG => rng::InterruptHandler<peripherals::RNG>;
 });
 
-pub type Flash = flash_nrf::Flash<'static>;
+#[cfg(not(feature = "usb"))]
+bind_interrupts!(struct Irqs {
+	SAADC => saadc::InterruptHandler;
+	QSPI => qspi::InterruptHandler<peripherals::QSPI>;
+	RNG => rng::InterruptHandler<peripherals::RNG>;
+});
+
+pub type Flash = storage::Storage;
 pub const PUBSUB_CAPACITY: usize = 20 * size_of::<ReactorEvent>();
 pub const PUBSUB_SUBSCRIBERS: usize = 4;
 pub const PUBSUB_PUBLISHERS: usize = 4;
 pub static CHANNEL: PubSubChannel<
 	CriticalSectionRawMutex,
-	ReactorEvent,
+	EventEnvelope,
 	PUBSUB_CAPACITY,
 	PUBSUB_SUBSCRIBERS,
 	PUBSUB_PUBLISHERS,
 > = PubSubChannel::new();
+/// Maximum number of subscribers a `subscribers_task` can fan out to
+pub const MAX_SUBSCRIBERS: usize = 4;
+/// Events each subscriber can have pending before its overflow policy kicks in
+pub const SUBSCRIBER_QUEUE_SIZE: usize = 8;
+/// Lag of the subscribers task behind `CHANNEL`
+pub static CHANNEL_DIAGNOSTICS: Diagnostics = Diagnostics::new();
+/// Per-subscriber delivery counters, in the order the subscribers were given to `subscribers_task`
+pub static SUBSCRIBER_DIAGNOSTICS: [Diagnostics; MAX_SUBSCRIBERS] = {
+	#[allow(clippy::declare_interior_mutable_const)]
+	const EMPTY: Diagnostics = Diagnostics::new();
+	[EMPTY; MAX_SUBSCRIBERS]
+};
+// The SoftDevice owns the POWER peripheral, so it forwards the USB power events to us
+#[cfg(all(feature = "usb", feature = "ble"))]
 lazy_static! {
-	// TODO: Add support for HardwareVbusDetect as well to avoid needing the SoftDevice
 	pub static ref VBUS_DETECT: SoftwareVbusDetect = SoftwareVbusDetect::new(true, true);
 }
+#[cfg(all(feature = "usb", not(feature = "ble")))]
+lazy_static! {
+	pub static ref VBUS_DETECT: HardwareVbusDetect = HardwareVbusDetect::new(Irqs);
+}
 
 pub fn init() -> embassy_nrf::Peripherals {
 	info!("PubSubinator v{}", env!("CARGO_PKG_VERSION"));
@@ -100,12 +162,14 @@ pub fn init() -> embassy_nrf::Peripherals {
 	embassy_nrf::init(config)
 }
 
+#[cfg(feature = "ble")]
 #[task]
 pub async fn softdevice_task(sd: &'static Softdevice) {
 	info!("SoftDevice task started");
 	sd.run_with_callback(|event: SocEvent| {
 		info!("SoftDevice event: {:?}", event);
 
+		#[cfg(feature = "usb")]
 		match event {
 			SocEvent::PowerUsbRemoved => VBUS_DETECT.detected(false),
 			SocEvent::PowerUsbDetected => VBUS_DETECT.detected(true),
@@ -117,18 +181,7 @@ pub async fn softdevice_task(sd: &'static Softdevice) {
 	info!("SoftDevice task finished");
 }
 
-#[task]
-pub async fn poller_task(poller: &'static mut dyn Polled) {
-	let mut ticker = Ticker::every(Duration::from_millis(MATRIX_PERIOD));
-	info!("Poller task started");
-
-	loop {
-		// TODO: Turn this into a join of all pollers
-		poller.poll().await;
-		ticker.next().await;
-	}
-}
-
+#[cfg(feature = "ble")]
 pub fn get_softdevice() -> &'static mut Softdevice {
 	info!("Starting SoftDevice BLE shit");
 
@@ -144,22 +197,22 @@ pub fn get_softdevice() -> &'static mut Softdevice {
 			accuracy: raw::NRF_CLOCK_LF_ACCURACY_20_PPM as u8,
 		}),
 		conn_gap: Some(raw::ble_gap_conn_cfg_t {
-			conn_count: 6,
-			event_length: 24,
+			conn_count: config::BLE.peripheral_connections + config::BLE.central_connections,
+			event_length: config::BLE.event_length,
 		}),
-		conn_gatt: Some(raw::ble_gatt_conn_cfg_t { att_mtu: 256 }), // Got from a trace! that said that peer wants 517
+		conn_gatt: Some(raw::ble_gatt_conn_cfg_t { att_mtu: config::BLE.att_mtu }),
 		gatts_attr_tab_size: Some(raw::ble_gatts_cfg_attr_tab_size_t { attr_tab_size: 32768 }),
 		gap_role_count: Some(raw::ble_gap_cfg_role_count_t {
 			adv_set_count: 1,
-			periph_role_count: 3,
-			central_role_count: 3,
-			central_sec_count: 3,
+			periph_role_count: config::BLE.peripheral_connections,
+			central_role_count: config::BLE.central_connections,
+			central_sec_count: config::BLE.central_connections,
 			_bitfield_1: raw::ble_gap_cfg_role_count_t::new_bitfield_1(0),
 		}),
 		gap_device_name: Some(raw::ble_gap_cfg_device_name_t {
-			p_value: env!("DEVICE_NAME").as_ptr() as _,
-			current_len: env!("DEVICE_NAME").len() as u16,
-			max_len: env!("DEVICE_NAME").len() as u16,
+			p_value: config::BLE.name.as_ptr() as _,
+			current_len: config::BLE.name.len() as u16,
+			max_len: config::BLE.name.len() as u16,
 			write_perm: unsafe { mem::zeroed() },
 			// TODO: Use the SecurityMode enum
 			// write_perm: raw::ble_gap_conn_sec_mode_t {
@@ -176,7 +229,9 @@ pub fn get_softdevice() -> &'static mut Softdevice {
 	sd
 }
 
-pub async fn get_db() -> &'static mut Database<&'static mut flash_nrf::Flash<'static>, CriticalSectionRawMutex> {
+pub async fn get_db(
+	#[cfg(feature = "ble")] sd: &Softdevice,
+) -> &'static Database<&'static mut Flash, CriticalSectionRawMutex> {
 	// --- Set the session seed ---
 	// TODO: This crashes with `sd_softdevice_enable err SdmIncorrectInterruptConfiguration`
 	// let mut rng = embassy_nrf::rng::Rng::new(p.RNG, crate::Irqs);
@@ -184,7 +239,13 @@ pub async fn get_db() -> &'static mut Database<&'static mut flash_nrf::Flash<'st
 	// TODO: For some reason dropping silently crashes - has to do with the SoftDevice?
 	// drop(rng); // Release the RNG as soon as possible
 
-	let flash = make_static!(flash_nrf::Flash::new().await);
+	let flash = make_static!(unwrap!(
+		Flash::new(
+			#[cfg(feature = "ble")]
+			sd
+		)
+		.await
+	));
 	let mut db_config = ekv::Config::default();
 	db_config.random_seed = 0xDEADBEEF;
 	let db = make_static!(ekv::Database::<_, CriticalSectionRawMutex>::new(flash, db_config));
@@ -196,5 +257,16 @@ pub async fn get_db() -> &'static mut Database<&'static mut flash_nrf::Flash<'st
 		info!("EKV Database formatted");
 	}
 
+	match db.mount().await {
+		Ok(()) => {},
+		// A blank flash has to be formatted first
+		Err(ekv::MountError::Corrupted) => {
+			warn!("The EKV Database is blank or corrupted, formatting it");
+			unwrap!(db.format().await);
+		},
+		// Formatting would throw the bonds away over what may only be a glitch of the flash
+		Err(ekv::MountError::Flash(e)) => defmt::panic!("Could not mount the EKV Database: {:?}", e),
+	}
+
 	db
 }
@@ -7,13 +7,18 @@ use alloc::vec;
 use alloc::vec::Vec;
 use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
 use embassy_sync::pubsub::Publisher;
+use embassy_time::{Duration, Instant};
 use embedded_hal::digital::{InputPin, OutputPin};
 use futures::Future;
 use reactor::reactor_event::*;
 use reactor::{Polled, RPublisher};
 use strum::EnumString;
 
+pub const MATRIX_SOURCE: SourceId = SourceId(1);
 pub const MATRIX_PERIOD: u64 = 2;
+/// Period used once the matrix has been idle for `MATRIX_IDLE_AFTER` - 0 disables idle polling
+pub const MATRIX_IDLE_PERIOD: u64 = 0;
+pub const MATRIX_IDLE_AFTER: u64 = 5000;
 // pub const HOLD_CYCLES: u8 = 200;
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, EnumString)]
@@ -30,8 +35,12 @@ pub struct Matrix<'a, I: InputPin<Error = Infallible>, O: OutputPin<Error = Infa
 	outputs: Vec<O>,
 	last_state: Vec<Vec<bool>>,
 	direction: MatrixDirection,
+	period: Duration,
+	idle_period: Option<Duration>,
+	idle_after: Duration,
+	last_activity: Instant,
 	channel:
-		Publisher<'a, CriticalSectionRawMutex, ReactorEvent, PUBSUB_CAPACITY, PUBSUB_SUBSCRIBERS, PUBSUB_PUBLISHERS>,
+		Publisher<'a, CriticalSectionRawMutex, EventEnvelope, PUBSUB_CAPACITY, PUBSUB_SUBSCRIBERS, PUBSUB_PUBLISHERS>,
 }
 
 impl<'a, I: InputPin<Error = Infallible>, O: OutputPin<Error = Infallible>> Matrix<'a, I, O> {
@@ -43,10 +52,26 @@ impl<'a, I: InputPin<Error = Infallible>, O: OutputPin<Error = Infallible>> Matr
 			outputs,
 			last_state,
 			direction,
+			period: Duration::from_millis(MATRIX_PERIOD),
+			idle_period: None,
+			idle_after: Duration::from_millis(MATRIX_IDLE_AFTER),
+			last_activity: Instant::now(),
 			channel: crate::CHANNEL.publisher().unwrap(),
 		}
 	}
 
+	pub fn with_period(mut self, period: Duration) -> Self {
+		self.period = period;
+		self
+	}
+
+	/// Poll every `idle_period` after no key changed state for `idle_after`
+	pub fn with_idle_period(mut self, idle_period: Duration, idle_after: Duration) -> Self {
+		self.idle_period = Some(idle_period);
+		self.idle_after = idle_after;
+		self
+	}
+
 	fn read(&mut self, index: usize) -> bool {
 		self.inputs[index].is_high().unwrap()
 	}
@@ -69,6 +94,9 @@ impl<'a, I: InputPin<Error = Infallible>, O: OutputPin<Error = Infallible>> Poll
 			let num_inputs = self.inputs.len();
 			let num_outputs = self.outputs.len();
 
+			// All the keys of a scan happened at the same time
+			let timestamp = Instant::now();
+
 			for oi in 0..num_outputs {
 				self.write(oi, true);
 
@@ -84,16 +112,27 @@ impl<'a, I: InputPin<Error = Infallible>, O: OutputPin<Error = Infallible>> Poll
 						self.last_state[row][col] = state;
 
 						let event = ReactorEvent::HardwareMappedBool(state, row, col);
-						event_buffer.push(event);
+						event_buffer.push(EventEnvelope::at(event, MATRIX_SOURCE, timestamp));
 					}
 				}
 
 				self.write(oi, false);
 			}
 
+			if !event_buffer.is_empty() {
+				self.last_activity = timestamp;
+			}
+
 			for event in event_buffer {
 				self.channel.publish(event).await;
 			}
 		})
 	}
+
+	fn period(&self) -> Duration {
+		match self.idle_period {
+			Some(idle_period) if self.last_activity.elapsed() > self.idle_after => idle_period,
+			_ => self.period,
+		}
+	}
 }
@@ -3,15 +3,41 @@ use embassy_executor::task;
 use embassy_nrf::interrupt::{InterruptExt, Priority};
 use embassy_nrf::pac::Interrupt;
 use embassy_nrf::peripherals;
+#[cfg(not(feature = "ble"))]
+use embassy_nrf::usb::vbus_detect::HardwareVbusDetect;
+#[cfg(feature = "ble")]
 use embassy_nrf::usb::vbus_detect::SoftwareVbusDetect;
 use embassy_nrf::usb::Driver;
-use embassy_usb::{Builder, Config};
+use embassy_usb::{Builder, Config, Handler};
 use static_cell::make_static;
 
-use crate::{Irqs, VBUS_DETECT};
+use crate::output::OUTPUT;
+use crate::{config, Irqs, VBUS_DETECT};
 
-// TODO: Choose between SoftwareVbusDetect and HardwareVbusDetect - hardware if SoftDevice is not used
+#[cfg(feature = "ble")]
 pub type UsbDriver = Driver<'static, peripherals::USBD, &'static SoftwareVbusDetect>;
+#[cfg(not(feature = "ble"))]
+pub type UsbDriver = Driver<'static, peripherals::USBD, &'static HardwareVbusDetect>;
+
+/// Tells the output router whether the host enumerated the board
+struct UsbState;
+
+impl Handler for UsbState {
+	fn enabled(&mut self, enabled: bool) {
+		if !enabled {
+			OUTPUT.set_usb_configured(false);
+		}
+	}
+
+	fn reset(&mut self) {
+		OUTPUT.set_usb_configured(false);
+	}
+
+	fn configured(&mut self, configured: bool) {
+		info!("USB {}", if configured { "configured" } else { "unconfigured" });
+		OUTPUT.set_usb_configured(configured);
+	}
+}
 
 #[task]
 pub async fn usb_task(builder: Builder<'static, UsbDriver>) {
@@ -29,15 +55,16 @@ pub fn usb_init(p_usbd: peripherals::USBD) -> Builder<'static, UsbDriver> {
 
 	let driver = Driver::new(p_usbd, Irqs, &*VBUS_DETECT);
 
-	let mut usb_config = Config::new(0xC0DE, 0xCAFE);
-	usb_config.manufacturer = Some("PubSubinator");
-	usb_config.product = Some(env!("DEVICE_NAME"));
-	usb_config.serial_number = Some(env!("DEVICE_SERIAL"));
-	usb_config.max_power = 100;
+	let mut usb_config = Config::new(config::USB.vendor_id, config::USB.product_id);
+	usb_config.manufacturer = Some(config::USB.manufacturer);
+	usb_config.product = Some(config::USB.product);
+	usb_config.serial_number = Some(config::USB.serial_number);
+	usb_config.device_release = config::USB.device_release;
+	usb_config.max_power = config::USB.max_power;
 	usb_config.max_packet_size_0 = 64;
 	usb_config.supports_remote_wakeup = true;
 
-	let builder = Builder::new(
+	let mut builder = Builder::new(
 		driver,
 		usb_config,
 		&mut make_static!([0; 256])[..],
@@ -46,6 +73,7 @@ pub fn usb_init(p_usbd: peripherals::USBD) -> Builder<'static, UsbDriver> {
 		&mut make_static!([0; 128])[..],
 		&mut make_static!([0; 128])[..],
 	);
+	builder.handler(make_static!(UsbState));
 
 	builder
 }
@@ -0,0 +1,161 @@
+//! Which of USB and BLE the reports go out on when the board has both, so a plugged in board
+//! doesn't type everything twice
+
+use core::str::FromStr;
+use core::sync::atomic::{AtomicBool, AtomicU8, Ordering};
+
+use defmt::*;
+use lazy_static::lazy_static;
+use strum::EnumString;
+
+use crate::{config, CHANNEL};
+use reactor::reactor_event::*;
+
+pub const OUTPUT_SOURCE: SourceId = SourceId(7);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Format, EnumString)]
+#[repr(u8)]
+pub enum OutputMode {
+	/// USB once the host enumerated the board, BLE otherwise
+	Usb,
+	/// BLE once a host connected, USB otherwise
+	Ble,
+	/// Both at once
+	Both,
+}
+
+impl OutputMode {
+	fn from_u8(mode: u8) -> Self {
+		match mode {
+			0 => Self::Usb,
+			1 => Self::Ble,
+			_ => Self::Both,
+		}
+	}
+}
+
+/// One of the transports, as its bit in the `Router` states - `usb | ble << 1`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Format)]
+#[repr(u8)]
+pub enum Transport {
+	Usb = 1,
+	Ble = 1 << 1,
+}
+
+/// Picks the transports from `output.mode` and what's connected, and publishes them as `Output`
+/// whenever they change
+pub struct Router {
+	mode: AtomicU8,
+	usb_configured: AtomicBool,
+	ble_connected: AtomicBool,
+	/// Last published `Output`, `usb` and `ble` bits
+	published: AtomicU8,
+	/// Transports `update` dropped that still have to release what their host sees held
+	releasing: AtomicU8,
+	/// No reports go out on any transport, e.g. while a passkey is typed on the board
+	held: AtomicBool,
+}
+
+lazy_static! {
+	pub static ref OUTPUT: Router = Router::new(OutputMode::from_str(config::OUTPUT.mode).unwrap());
+}
+
+impl Router {
+	fn new(mode: OutputMode) -> Self {
+		Self {
+			mode: AtomicU8::new(mode as u8),
+			usb_configured: AtomicBool::new(false),
+			ble_connected: AtomicBool::new(false),
+			// Nothing yet, so the first state gets published
+			published: AtomicU8::new(u8::MAX),
+			releasing: AtomicU8::new(0),
+			held: AtomicBool::new(false),
+		}
+	}
+
+	pub fn mode(&self) -> OutputMode {
+		OutputMode::from_u8(self.mode.load(Ordering::Relaxed))
+	}
+
+	/// Whether the reports go out on USB
+	pub fn to_usb(&self) -> bool {
+		match self.mode() {
+			OutputMode::Usb => self.usb_configured.load(Ordering::Relaxed),
+			OutputMode::Ble => !self.ble_connected.load(Ordering::Relaxed),
+			OutputMode::Both => true,
+		}
+	}
+
+	/// Whether the reports go out on BLE
+	pub fn to_ble(&self) -> bool {
+		match self.mode() {
+			OutputMode::Usb => !self.usb_configured.load(Ordering::Relaxed),
+			OutputMode::Ble => self.ble_connected.load(Ordering::Relaxed),
+			OutputMode::Both => true,
+		}
+	}
+
+	/// Whether the reports are held back from every transport
+	pub fn held(&self) -> bool {
+		self.held.load(Ordering::Relaxed)
+	}
+
+	/// Hold the reports back from every transport, or let them go again
+	pub fn hold(&self, held: bool) {
+		if self.held.swap(held, Ordering::Relaxed) != held {
+			debug!("Holding the reports back: {}", held);
+		}
+	}
+
+	/// Whether `transport` was dropped with keys maybe still held on its host, for its subscriber to
+	/// send empty reports on `Output` - only once, even if several `Output` were published since
+	pub fn take_release(&self, transport: Transport) -> bool {
+		let bit = transport as u8;
+		self.releasing.fetch_and(!bit, Ordering::Relaxed) & bit != 0
+	}
+
+	/// Cycle the preference from USB to BLE to both and back to USB
+	pub fn toggle(&self) {
+		let mode = match self.mode() {
+			OutputMode::Usb => OutputMode::Ble,
+			OutputMode::Ble => OutputMode::Both,
+			OutputMode::Both => OutputMode::Usb,
+		};
+		info!("Switching the output mode to {}", mode);
+
+		self.mode.store(mode as u8, Ordering::Relaxed);
+		self.update();
+	}
+
+	/// The USB host configured the board, or let it go
+	pub fn set_usb_configured(&self, configured: bool) {
+		self.usb_configured.store(configured, Ordering::Relaxed);
+		self.update();
+	}
+
+	/// A BLE host connected, or went away
+	pub fn set_ble_connected(&self, connected: bool) {
+		self.ble_connected.store(connected, Ordering::Relaxed);
+		self.update();
+	}
+
+	fn update(&self) {
+		let (usb, ble) = (self.to_usb(), self.to_ble());
+		let bits = (usb as u8) | (ble as u8) << 1;
+		let old = self.published.swap(bits, Ordering::Relaxed);
+		if old == bits {
+			return;
+		}
+		// Keys held on a dropped transport would stay stuck on its host, so its subscriber sends
+		// empty reports when it gets the `Output`
+		if old != u8::MAX {
+			self.releasing.fetch_or(old & !bits, Ordering::Relaxed);
+		}
+
+		info!("Output to USB: {}, BLE: {}", usb, ble);
+		// Called from the USB and SoftDevice callbacks, which can't wait
+		CHANNEL
+			.immediate_publisher()
+			.publish_immediate(EventEnvelope::new(ReactorEvent::Output { usb, ble }, OUTPUT_SOURCE));
+	}
+}
@@ -1,12 +1,19 @@
 pub use crate::analog_nrf::Analog;
+pub use crate::battery_nrf::Battery;
+#[cfg(feature = "ble")]
+pub use crate::ble_central::BleCentral;
+#[cfg(feature = "ble")]
 pub use crate::ble_hid::{ble_hid_task, BleHid};
 pub use crate::config_types::ConfigBuilder;
+#[cfg(feature = "usb")]
 pub use crate::nrf::{usb_init, usb_task};
+#[cfg(feature = "usb")]
 pub use crate::usb_hid::UsbHid;
 
-pub use defmt::info;
+pub use defmt::{info, warn};
 pub use embassy_executor::Spawner;
+pub use embassy_time::Duration;
 pub use embassy_nrf::saadc;
 pub use crate::*;
-pub use reactor_macros::{subscribers_task, subscribers_task_env};
+pub use reactor_macros::{board_main, publishers_task, subscribers_task, subscribers_task_env};
 pub use static_cell::make_static;
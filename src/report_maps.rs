@@ -1,5 +1,8 @@
-pub use usbd_hid::descriptor::*;
+use defmt::Format;
+use reactor::KeyCode;
 use serde::ser::{Serialize, SerializeTuple, Serializer};
+use strum::EnumString;
+pub use usbd_hid::descriptor::*;
 
 #[gen_hid_descriptor(
 	(collection = APPLICATION, usage_page = GENERIC_DESKTOP, usage = MULTI_AXIS_CONTROLLER) = {
@@ -42,3 +45,58 @@ pub struct SpaceMouseReport {
 	pub rz: i16,
 	pub buttons: u8,
 }
+
+/// Reports of this module that can share a report map, each under its own report ID
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Format, EnumString)]
+pub enum Report {
+	KeyboardReport,
+	MediaKeyboardReport,
+	MouseReport,
+	SpaceMouseReport,
+}
+
+/// Longest input report of `Report`
+pub const REPORT_SIZE_MAX: usize = 13;
+
+impl Report {
+	pub fn desc(self) -> &'static [u8] {
+		match self {
+			Self::KeyboardReport => KeyboardReport::desc(),
+			Self::MediaKeyboardReport => MediaKeyboardReport::desc(),
+			Self::MouseReport => MouseReport::desc(),
+			Self::SpaceMouseReport => SpaceMouseReport::desc(),
+		}
+	}
+
+	/// Size of the serialized input report, which leaves out outputs like the keyboard LEDs
+	pub fn size(self) -> usize {
+		match self {
+			Self::KeyboardReport => 8,
+			Self::MediaKeyboardReport => 2,
+			Self::MouseReport => 5,
+			Self::SpaceMouseReport => REPORT_SIZE_MAX,
+		}
+	}
+}
+
+/// Consumer control usage of the media keys, for `MediaKeyboardReport`
+pub fn consumer_usage(key: KeyCode) -> Option<u16> {
+	Some(match key {
+		KeyCode::MediaPlayPause => 0xCD,
+		KeyCode::MediaStopCD | KeyCode::MediaStop => 0xB7,
+		KeyCode::MediaPreviousSong => 0xB6,
+		KeyCode::MediaNextSong => 0xB5,
+		KeyCode::MediaEjectCD => 0xB8,
+		KeyCode::MediaVolUp => 0xE9,
+		KeyCode::MediaVolDown => 0xEA,
+		KeyCode::MediaMute => 0xE2,
+		KeyCode::MediaWWW => 0x196,
+		KeyCode::MediaBack => 0x224,
+		KeyCode::MediaForward => 0x225,
+		KeyCode::MediaFind => 0x221,
+		KeyCode::MediaSleep => 0x32,
+		KeyCode::MediaRefresh => 0x227,
+		KeyCode::MediaCalc => 0x192,
+		_ => return None,
+	})
+}
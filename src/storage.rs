@@ -0,0 +1,67 @@
+//! Flash the `ekv` database lives on, picked by `flash.backend` of the board
+
+use defmt::Format;
+use ekv::flash::{Flash, PageID};
+#[cfg(feature = "ble")]
+use nrf_softdevice::Softdevice;
+
+use crate::flash_nrf::{self, FlashError};
+use crate::internal_flash_nrf::{InternalFlash, InternalFlashError};
+
+pub enum Storage {
+	/// External chip described by `[flash]`
+	Qspi(flash_nrf::Flash<'static>),
+	/// `STORAGE` region of `memory.x`
+	Internal(InternalFlash),
+}
+
+/// Error of the backend the database is on
+#[derive(Debug, Clone, Copy, Format)]
+pub enum StorageError {
+	Qspi(FlashError),
+	Internal(InternalFlashError),
+}
+
+impl Storage {
+	pub async fn new(#[cfg(feature = "ble")] sd: &Softdevice) -> Result<Self, FlashError> {
+		match crate::config::FLASH.backend {
+			"internal" => Ok(Self::Internal(InternalFlash::new(
+				#[cfg(feature = "ble")]
+				sd,
+			))),
+			_ => Ok(Self::Qspi(flash_nrf::Flash::new().await?)),
+		}
+	}
+}
+
+impl Flash for Storage {
+	type Error = StorageError;
+
+	fn page_count(&self) -> usize {
+		match self {
+			Self::Qspi(flash) => flash.page_count(),
+			Self::Internal(flash) => flash.page_count(),
+		}
+	}
+
+	async fn erase(&mut self, page_id: PageID) -> Result<(), Self::Error> {
+		match self {
+			Self::Qspi(flash) => flash.erase(page_id).await.map_err(StorageError::Qspi),
+			Self::Internal(flash) => flash.erase(page_id).await.map_err(StorageError::Internal),
+		}
+	}
+
+	async fn read(&mut self, page_id: PageID, offset: usize, data: &mut [u8]) -> Result<(), Self::Error> {
+		match self {
+			Self::Qspi(flash) => flash.read(page_id, offset, data).await.map_err(StorageError::Qspi),
+			Self::Internal(flash) => flash.read(page_id, offset, data).await.map_err(StorageError::Internal),
+		}
+	}
+
+	async fn write(&mut self, page_id: PageID, offset: usize, data: &[u8]) -> Result<(), Self::Error> {
+		match self {
+			Self::Qspi(flash) => flash.write(page_id, offset, data).await.map_err(StorageError::Qspi),
+			Self::Internal(flash) => flash.write(page_id, offset, data).await.map_err(StorageError::Internal),
+		}
+	}
+}
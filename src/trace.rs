@@ -0,0 +1,141 @@
+use core::cell::RefCell;
+use core::pin::Pin;
+
+use alloc::boxed::Box;
+use defmt::*;
+#[cfg(feature = "usb")]
+use embassy_executor::task;
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+use embassy_sync::blocking_mutex::Mutex;
+#[cfg(feature = "usb")]
+use embassy_usb::class::cdc_acm::{CdcAcmClass, State};
+#[cfg(feature = "usb")]
+use embassy_usb::driver::EndpointError;
+#[cfg(feature = "usb")]
+use embassy_usb::Builder;
+use futures::Future;
+#[cfg(feature = "usb")]
+use static_cell::make_static;
+
+#[cfg(feature = "usb")]
+use crate::nrf::UsbDriver;
+use crate::{CHANNEL_DIAGNOSTICS, SUBSCRIBER_DIAGNOSTICS};
+use reactor::queue::{DiagnosticsSnapshot, OverflowPolicy};
+use reactor::reactor_event::*;
+use reactor::trace::{encode, TraceBuffer, RECORD_SIZE_MAX};
+use reactor::RSubscriber;
+
+/// Number of events kept in the trace ring buffer
+pub const TRACE_CAPACITY: usize = 64;
+
+pub static TRACE: Mutex<CriticalSectionRawMutex, RefCell<TraceBuffer<TRACE_CAPACITY>>> =
+	Mutex::new(RefCell::new(TraceBuffer::new()));
+
+/// Records every event of the channel in `TRACE`, and logs it with the delivery counters on `TraceDump`
+#[derive(Debug, Default)]
+pub struct Tracer;
+
+impl RSubscriber for Tracer {
+	// Tracing should never slow anything down
+	fn overflow_policy(&self) -> OverflowPolicy {
+		OverflowPolicy::DropOldest
+	}
+
+	fn push(&mut self, value: EventEnvelope) -> Pin<Box<dyn Future<Output = ()> + '_>> {
+		Box::pin(async move {
+			TRACE.lock(|trace| trace.borrow_mut().record(value));
+
+			if value.event == ReactorEvent::Internal(InternalEvent::TraceDump) {
+				dump_defmt();
+				dump_diagnostics();
+			}
+		})
+	}
+}
+
+/// Log the whole capture over defmt, both decoded and in the binary capture format
+pub fn dump_defmt() {
+	TRACE.lock(|trace| {
+		let trace = trace.borrow();
+		info!("[trace] {} events, {} overwritten", trace.len(), trace.overwritten());
+
+		let mut buf = [0u8; RECORD_SIZE_MAX];
+		for record in trace.iter() {
+			let len = encode(record, &mut buf);
+			info!("[trace] {:?} {=[u8]:x}", record, &buf[..len]);
+		}
+	});
+}
+
+/// Log how far the subscribers task lagged behind the channel, and what each subscriber queue
+/// delivered, dropped and coalesced
+pub fn dump_diagnostics() {
+	info!("[diagnostics] channel: {}", CHANNEL_DIAGNOSTICS.snapshot());
+	for (index, diagnostics) in SUBSCRIBER_DIAGNOSTICS.iter().enumerate() {
+		let snapshot = diagnostics.snapshot();
+		// Slots past the board's subscribers never count anything
+		if snapshot != DiagnosticsSnapshot::default() {
+			info!("[diagnostics] subscriber {}: {}", index, snapshot);
+		}
+	}
+}
+
+/// Serial port the capture gets dumped to
+#[cfg(feature = "usb")]
+pub fn usb_class(builder: &mut Builder<'static, UsbDriver>) -> CdcAcmClass<'static, UsbDriver> {
+	let state = make_static!(State::new());
+	CdcAcmClass::new(builder, state, 64)
+}
+
+/// Dumps the capture in the binary format every time the host opens the port or sends `d`,
+/// `c` clears it
+#[cfg(feature = "usb")]
+#[task]
+pub async fn trace_usb_task(mut class: CdcAcmClass<'static, UsbDriver>) {
+	info!("Trace USB task started");
+
+	loop {
+		class.wait_connection().await;
+		info!("[trace] Host connected");
+
+		let _ = dump_usb(&mut class).await;
+
+		let mut command = [0u8; 64];
+		loop {
+			match class.read_packet(&mut command).await {
+				Ok(len) =>
+					for byte in &command[..len] {
+						match byte {
+							b'd' => {
+								let _ = dump_usb(&mut class).await;
+							},
+							b'c' => TRACE.lock(|trace| trace.borrow_mut().clear()),
+							_ => {},
+						}
+					},
+				Err(EndpointError::Disabled) => break,
+				Err(e) => warn!("[trace] Error reading from USB: {:?}", e),
+			}
+		}
+
+		info!("[trace] Host disconnected");
+	}
+}
+
+#[cfg(feature = "usb")]
+async fn dump_usb(class: &mut CdcAcmClass<'static, UsbDriver>) -> Result<(), EndpointError> {
+	let len = TRACE.lock(|trace| trace.borrow().len());
+	let mut buf = [0u8; RECORD_SIZE_MAX];
+
+	// One record per packet, so the lock isn't held while writing
+	for index in 0..len {
+		let Some(record) = TRACE.lock(|trace| trace.borrow().iter().nth(index).copied()) else {
+			break;
+		};
+
+		let record_len = encode(&record, &mut buf);
+		class.write_packet(&buf[..record_len]).await?;
+	}
+
+	Ok(())
+}
@@ -1,21 +1,40 @@
-use core::ops::Deref;
 use core::pin::Pin;
 
 use alloc::boxed::Box;
 use defmt::*;
 use embassy_nrf::usb::vbus_detect::VbusDetect;
-use embassy_usb::class::hid::{HidWriter, State};
+use embassy_usb::class::hid::{HidWriter, ReportId, RequestHandler, State};
+use embassy_usb::control::OutResponse;
 use embassy_usb::Builder;
 use futures::Future;
 use static_cell::make_static;
 use usbd_hid::descriptor::{KeyboardReport, SerializedDescriptor};
 
 use crate::nrf::UsbDriver;
+use crate::output::{Transport, OUTPUT};
 use crate::report_maps::SpaceMouseReport;
-use crate::VBUS_DETECT;
+use crate::{config, CHANNEL};
 use reactor::reactor_event::*;
 use reactor::RSubscriber;
 
+pub const USB_HID_SOURCE: SourceId = SourceId(4);
+
+/// Publishes the LED output reports the host sends as `Locks`
+struct LedHandler;
+
+impl RequestHandler for LedHandler {
+	fn set_report(&mut self, id: ReportId, data: &[u8]) -> OutResponse {
+		if let (ReportId::Out(_), Some(&leds)) = (id, data.first()) {
+			debug!("Got the LEDs {:b} from USB", leds);
+			CHANNEL
+				.immediate_publisher()
+				.publish_immediate(EventEnvelope::new(ReactorEvent::from_leds(leds), USB_HID_SOURCE));
+		}
+
+		OutResponse::Accepted
+	}
+}
+
 pub struct UsbHid {
 	writer: Option<HidWriter<'static, UsbDriver, 8>>,
 }
@@ -27,8 +46,8 @@ impl UsbHid {
 		// Create classes on the builder.
 		let hid_config = embassy_usb::class::hid::Config {
 			report_descriptor: D::desc(),
-			request_handler: None,
-			poll_ms: 60,
+			request_handler: Some(make_static!(LedHandler)),
+			poll_ms: config::USB.poll_interval,
 			max_packet_size: 64,
 		};
 
@@ -42,17 +61,19 @@ impl UsbHid {
 impl RSubscriber for UsbHid {
 	fn is_supported(&self, event: ReactorEvent) -> bool {
 		(match event {
-			ReactorEvent::Key(_) => true,
+			ReactorEvent::KeyboardReport { .. } | ReactorEvent::Joystick6DoF { .. } =>
+				OUTPUT.to_usb() && !OUTPUT.held(),
+			// Even once USB is dropped, to release what the host still sees held
+			ReactorEvent::Output { .. } => true,
 			// ReactorEvent::Locks { caps, num, scroll } => true,
 			// ReactorEvent::Mouse { x, y } => true,
 			_ => false,
 		}) && self.writer.is_some()
-			&& VBUS_DETECT.deref().is_usb_detected()
 	}
 
-	fn push(&mut self, value: ReactorEvent) -> Pin<Box<dyn Future<Output = ()> + '_>> {
+	fn push(&mut self, value: EventEnvelope) -> Pin<Box<dyn Future<Output = ()> + '_>> {
 		Box::pin(async move {
-			match value {
+			match value.event {
 				ReactorEvent::KeyboardReport { modifier, keycodes } => {
 					let report = KeyboardReport {
 						modifier: modifier.into(),
@@ -90,7 +111,21 @@ impl RSubscriber for UsbHid {
 						Ok(_) => {},
 						Err(e) => warn!("Error writing to USB HID: {:?}", e),
 					}
-				}
+				},
+				// Keys held when USB was dropped would stay stuck on the host
+				ReactorEvent::Output { usb: false, .. } if OUTPUT.take_release(Transport::Usb) => {
+					let report = KeyboardReport {
+						modifier: 0,
+						reserved: 0,
+						leds: 0,
+						keycodes: [0; 6],
+					};
+
+					match self.writer.as_mut().unwrap().write_serialize(&report).await {
+						Ok(_) => {},
+						Err(e) => warn!("Error writing to USB HID: {:?}", e),
+					}
+				},
 				_ => return,
 			}
 		})
@@ -0,0 +1,140 @@
+//! Host-side tool for event captures dumped by the firmware tracer
+//!
+//! Reads a binary capture (e.g. `cat /dev/ttyACM0 > capture.bin`) and prints it as a timeline.
+//! With `--replay <board.json>` the capture is also fed through the board's `keymap` and
+//! `keyboard_report` middleware with `reactor::trace::replay` on a virtual clock, so the same input
+//! always produces the same output. The board comes from the manifest the build script writes.
+
+use std::{env, fs, process};
+
+use embassy_time::Duration;
+use reactor::keyboard_report::KeyboardReportMid;
+use reactor::keymap::{Keymap, KEYMAP_SOURCE};
+use reactor::middleware::Middleware;
+use reactor::trace::{decode_all, replay};
+use reactor::{KeyCodeInt, ReactorEvent};
+use serde::Deserialize;
+
+/// The parts of the board manifest (`board.json`) the replay needs
+#[derive(Deserialize)]
+struct Manifest {
+	middleware: Vec<String>,
+	keymap: Option<KeymapManifest>,
+}
+
+#[derive(Deserialize)]
+struct KeymapManifest {
+	layers: Vec<Vec<Vec<String>>>,
+	hold_time: Option<u64>,
+}
+
+fn usage() -> ! {
+	eprintln!("Usage: trace-replay [--replay <board.json>] <capture.bin>");
+	process::exit(1);
+}
+
+fn fail(message: String) -> ! {
+	eprintln!("{}", message);
+	process::exit(1);
+}
+
+fn main() {
+	let mut args = env::args().skip(1);
+	let mut board = None;
+	let mut path = None;
+	while let Some(arg) = args.next() {
+		match arg.as_str() {
+			"--replay" => board = Some(args.next().unwrap_or_else(|| usage())),
+			_ if arg.starts_with("--") || path.is_some() => usage(),
+			_ => path = Some(arg),
+		}
+	}
+	let Some(path) = path else {
+		usage();
+	};
+
+	let capture = fs::read(&path).unwrap_or_else(|e| fail(format!("Could not read capture {}: {}", path, e)));
+
+	let records = decode_all(&capture);
+	println!("{} events", records.len());
+
+	let start = records.first().map(|r| r.timestamp.as_millis()).unwrap_or(0);
+	for record in &records {
+		println!(
+			"+{:>8}ms [source {:>3}] {:?}",
+			record.timestamp.as_millis().saturating_sub(start),
+			record.source.0,
+			record.event
+		);
+	}
+
+	if let Some(board) = board {
+		let manifest = fs::read_to_string(&board)
+			.map_err(|e| e.to_string())
+			.and_then(|manifest| serde_json::from_str::<Manifest>(&manifest).map_err(|e| e.to_string()))
+			.unwrap_or_else(|e| fail(format!("Could not read board manifest {}: {}", board, e)));
+
+		let mut keymap = None;
+		let mut keyboard_report = None;
+		for name in &manifest.middleware {
+			match name.as_str() {
+				"keymap" => keymap = Some(build_keymap(&manifest)),
+				"keyboard_report" => keyboard_report = Some(KeyboardReportMid::default()),
+				_ => eprintln!("Middleware `{}` can't run on the host, skipping it", name),
+			}
+		}
+
+		// What the replayed middleware made during the capture gets made again
+		let (replay_keymap, replay_reports) = (keymap.is_some(), keyboard_report.is_some());
+		let records = records.into_iter().filter(|record| {
+			!(replay_keymap && record.source == KEYMAP_SOURCE
+				|| replay_reports && matches!(record.event, ReactorEvent::KeyboardReport { .. }))
+		});
+
+		let mut middleware: Vec<&mut dyn Middleware> = Vec::new();
+		if let Some(keymap) = &mut keymap {
+			middleware.push(keymap);
+		}
+		if let Some(keyboard_report) = &mut keyboard_report {
+			middleware.push(keyboard_report);
+		}
+		let output = futures::executor::block_on(replay(records, &mut middleware));
+
+		println!("Replay produced {} events", output.len());
+		for record in &output {
+			println!(
+				"{:>9}ms [source {:>3}] {:?}",
+				record.timestamp.as_millis(),
+				record.source.0,
+				record.event
+			);
+		}
+	}
+}
+
+/// The keymap the same way the firmware builds it from `config::KEYMAP`
+fn build_keymap(manifest: &Manifest) -> Keymap {
+	let Some(keymap) = &manifest.keymap else {
+		fail("The board has the `keymap` middleware but no keymap".to_owned());
+	};
+
+	let layers = keymap
+		.layers
+		.iter()
+		.map(|layer| {
+			layer
+				.iter()
+				.map(|row| {
+					row.iter()
+						.map(|key| {
+							key.parse::<KeyCodeInt>()
+								.unwrap_or_else(|_| fail(format!("Unknown key `{}`", key)))
+						})
+						.collect()
+				})
+				.collect()
+		})
+		.collect();
+
+	Keymap::new(layers, Duration::from_millis(keymap.hold_time.unwrap_or(0)))
+}